@@ -0,0 +1,133 @@
+//! Route matching benchmarks. Run with `cargo bench --features nightly` on nightly Rust.
+//!
+//! `linear_*` scan regex `Matcher`s in order, as `Router` used to.
+//! `router_*` look up the route tree of `Router`.
+//!
+//! `Router` copies params and middlewares out of the walk only for the route taken.
+//! `router_candidates` walks many routes matching the path but not the method,
+//! and `router_mounted` walks mounted routers with middlewares, where copying per candidate would show.
+
+#![cfg_attr(feature = "nightly", feature(test))]
+
+#[cfg(feature = "nightly")]
+mod bench {
+    extern crate test;
+    extern crate zircon;
+
+    use std::sync::Arc;
+
+    use self::test::Bencher;
+    use self::zircon::handlers::router::Matcher;
+    use self::zircon::middleware::Middleware;
+    use self::zircon::hyper::Method;
+    use self::zircon::prelude::*;
+
+    type App = ZirconDefaultApp<()>;
+
+    const NUM_ROUTES: usize = 300;
+
+    fn ok(_app: Arc<App>, _req: Request) -> HandlerResult {
+        Response::new().render()
+    }
+
+    fn patterns() -> Vec<String> {
+        (0..NUM_ROUTES).map(|i| {
+            match i % 3 {
+                0 => format!("/resource{}", i),
+                1 => format!("/resource{}/:id", i),
+                _ => format!("/resource{}/:id/items/:item", i),
+            }
+        }).collect()
+    }
+
+    fn matchers() -> Vec<Matcher> {
+        patterns().iter().map(|p| Matcher::from(p.as_str())).collect()
+    }
+
+    fn router() -> Router<App> {
+        let mut router = Router::new();
+        for p in patterns() {
+            router.get(&p, ok);
+        }
+        router
+    }
+
+    struct Noop;
+
+    impl Middleware<App> for Noop {}
+
+    /// `/api/v1` has the routes of `router()`, each router with a middleware.
+    fn mounted_router() -> Router<App> {
+        let mut inner = router();
+        inner.middleware(Noop);
+        let mut api = Router::new();
+        api.middleware(Noop);
+        api.mount("/v1", inner);
+        let mut root = Router::new();
+        root.middleware(Noop);
+        root.mount("/api", api);
+        root
+    }
+
+    /// Routes `/items/:id0` to `/items/:id99` for POST, and `/items/:id` for GET at last.
+    fn candidates_router() -> Router<App> {
+        let mut router = Router::new();
+        for i in 0..100 {
+            router.post(&format!("/items/:id{}", i), ok);
+        }
+        router.get("/items/:id", ok);
+        router
+    }
+
+    fn linear(matchers: &[Matcher], path: &str) -> bool {
+        matchers.iter().any(|m| m.match_route(path).is_some())
+    }
+
+    #[bench]
+    fn linear_first(b: &mut Bencher) {
+        let matchers = matchers();
+        b.iter(|| linear(&matchers, "/resource0"));
+    }
+
+    #[bench]
+    fn linear_last(b: &mut Bencher) {
+        let matchers = matchers();
+        b.iter(|| linear(&matchers, "/resource299/3/items/4"));
+    }
+
+    #[bench]
+    fn linear_miss(b: &mut Bencher) {
+        let matchers = matchers();
+        b.iter(|| linear(&matchers, "/unknown/3"));
+    }
+
+    #[bench]
+    fn router_first(b: &mut Bencher) {
+        let router = router();
+        b.iter(|| router.recognize(&Method::Get, "/resource0").is_some());
+    }
+
+    #[bench]
+    fn router_last(b: &mut Bencher) {
+        let router = router();
+        b.iter(|| router.recognize(&Method::Get, "/resource299/3/items/4").is_some());
+    }
+
+    #[bench]
+    fn router_miss(b: &mut Bencher) {
+        let router = router();
+        b.iter(|| router.recognize(&Method::Get, "/unknown/3").is_some());
+    }
+
+    #[bench]
+    fn router_candidates(b: &mut Bencher) {
+        let router = candidates_router();
+        b.iter(|| router.recognize(&Method::Get, "/items/3").is_some());
+    }
+
+    #[bench]
+    fn router_mounted(b: &mut Bencher) {
+        let router = mounted_router();
+        b.iter(|| router.recognize(&Method::Get, "/api/v1/resource299/3/items/4").is_some());
+    }
+}
@@ -0,0 +1,27 @@
+extern crate zircon;
+
+use zircon::ListenAddr;
+use zircon::prelude::*;
+use std::sync::Arc;
+
+type App = ZirconDefaultApp<()>;
+
+fn handler(_app: Arc<App>, _req: Request) -> HandlerResult {
+    return Response::text("Hello, world over IPv4 or IPv6!").render();
+}
+
+fn main() {
+    let app = App::from_config(ZirconConfig::dev());
+    let server = Zircon::new(app, handler);
+
+    // Listens on [::]:3000 for both IPv6 and IPv4 clients, and on [::1]:3001 for IPv6 clients only.
+    let addrs = vec![
+        ListenAddr::dual_stack(3000),
+        ListenAddr::new("[::1]:3001".parse().unwrap()),
+    ];
+    let server = server.bind_all(addrs).unwrap();
+    for addr in server.local_addrs() {
+        println!("Access http://{}/", addr);
+    }
+    server.run().unwrap();
+}
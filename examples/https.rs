@@ -0,0 +1,36 @@
+extern crate zircon;
+
+use zircon::TlsConfig;
+use zircon::prelude::*;
+use std::io::BufRead;
+use std::sync::Arc;
+
+type App = ZirconDefaultApp<()>;
+
+fn handler(_app: Arc<App>, req: Request) -> HandlerResult {
+    let s = format!("Hello, world! scheme={:?}", req.scheme());
+    return Response::text(s).render();
+}
+
+// Create a self-signed certificate with:
+//   openssl req -x509 -newkey rsa:2048 -nodes -keyout key.pem -out cert.pem -subj /CN=localhost
+fn main() {
+    let tls = TlsConfig::new("cert.pem", "key.pem").unwrap();
+
+    // Reload certificates from disk every time Enter is pressed.
+    let tls2 = tls.clone();
+    std::thread::spawn(move || {
+        let stdin = std::io::stdin();
+        for _ in stdin.lock().lines() {
+            if let Err(err) = tls2.reload() {
+                println!("failed to reload certificates: {}", err);
+            }
+        }
+    });
+
+    let app = App::from_config(ZirconConfig::dev());
+    let server = Zircon::new(app, handler);
+    let addr = "127.0.0.1:3443".parse().unwrap();
+    println!("Access https://127.0.0.1:3443/ . Press Enter to reload certificates.");
+    server.https(&addr, tls).unwrap();
+}
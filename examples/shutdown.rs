@@ -0,0 +1,34 @@
+extern crate zircon;
+
+use zircon::prelude::*;
+use std::io::BufRead;
+use std::sync::Arc;
+use std::time::Duration;
+
+type App = ZirconDefaultApp<()>;
+
+fn handler_root(_app: Arc<App>, _req: Request) -> HandlerResult {
+    return Response::text("Hello, world (root)").render();
+}
+
+fn main() {
+    let config = ZirconConfig::dev()
+        .with_shutdown_timeout(Duration::from_secs(5));
+    let app = App::from_config(config);
+
+    let mut router = Router::new();
+    router.get("/", handler_root);
+
+    let server = Zircon::new(app, router);
+    let shutdown = server.shutdown_handle();
+    std::thread::spawn(move || {
+        let stdin = std::io::stdin();
+        let _ = stdin.lock().lines().next();
+        shutdown.shutdown();
+    });
+
+    let addr = "127.0.0.1:3000".parse().unwrap();
+    println!("Access http://127.0.0.1:3000/ . Press Enter to stop the server.");
+    server.http(&addr).unwrap();
+    println!("Server stopped.");
+}
@@ -0,0 +1,24 @@
+extern crate zircon;
+
+use zircon::prelude::*;
+use std::sync::Arc;
+
+type App = ZirconDefaultApp<()>;
+
+fn handler(_app: Arc<App>, _req: Request) -> HandlerResult {
+    return Response::text("Hello, world!").render();
+}
+
+// Run under a systemd socket unit, or try it with:
+//   systemd-socket-activate -l 3000 target/debug/examples/socket_activation
+// When started without socket activation, this listens on 127.0.0.1:3001 instead.
+fn main() {
+    let app = App::from_config(ZirconConfig::dev());
+    let server = Zircon::new(app, handler);
+    let addr = "127.0.0.1:3001".parse().unwrap();
+    let server = server.bind(&addr).unwrap();
+    for addr in server.local_addrs() {
+        println!("Access http://{}/", addr);
+    }
+    server.run().unwrap();
+}
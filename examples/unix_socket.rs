@@ -0,0 +1,22 @@
+extern crate zircon;
+
+use zircon::UnixListenAddr;
+use zircon::prelude::*;
+use std::sync::Arc;
+
+type App = ZirconDefaultApp<()>;
+
+fn handler(_app: Arc<App>, req: Request) -> HandlerResult {
+    let s = format!("Hello, world! remote_addr={:?}", req.remote_addr());
+    return Response::text(s).render();
+}
+
+fn main() {
+    let app = App::from_config(ZirconConfig::dev());
+    let server = Zircon::new(app, handler);
+
+    // Allow a reverse proxy running in the same group to connect.
+    let addr = UnixListenAddr::new("/tmp/zircon.sock").with_mode(0o660);
+    println!("Try: curl --unix-socket /tmp/zircon.sock http://localhost/");
+    server.unix(addr).unwrap();
+}
@@ -0,0 +1,25 @@
+extern crate zircon;
+
+use zircon::prelude::*;
+use std::sync::Arc;
+
+type App = ZirconDefaultApp<()>;
+
+fn handler(_app: Arc<App>, _req: Request) -> HandlerResult {
+    let s = format!("Hello, world from process {}!", std::process::id());
+    return Response::text(s).render();
+}
+
+// Send SIGUSR2 to replace the running server with a freshly started one:
+//   kill -USR2 <pid>
+// Requests keep being served during the upgrade.
+fn main() {
+    let config = ZirconConfig::dev()
+        .with_upgrade_on_signal(true);
+    let app = App::from_config(config);
+    let server = Zircon::new(app, handler);
+    let addr = "127.0.0.1:3000".parse().unwrap();
+    println!("Access http://127.0.0.1:3000/ . pid={}", std::process::id());
+    server.http(&addr).unwrap();
+    println!("Process {} stopped.", std::process::id());
+}
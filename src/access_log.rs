@@ -0,0 +1,450 @@
+use std::ascii::AsciiExt;
+use std::cell::Cell;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::mem;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use futures::sync::mpsc::SendError;
+use futures::{Future, Sink, Stream};
+use hyper::header::{ContentLength, Headers, Referer, UserAgent};
+use hyper::{self, Body, Chunk, HttpVersion, Method};
+use serde_json::{Map, Value as Json};
+use tokio_core::reactor::Handle;
+
+use HyperResponse;
+use prelude::*;
+
+header! { (XRequestId, "X-Request-Id") => [String] }
+
+static REQUEST_COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Makes an ID unique in this host: the process ID, the time and a counter.
+fn new_request_id() -> String {
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0);
+    let count = REQUEST_COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("{:x}-{:x}-{:x}", process::id(), secs, count)
+}
+
+/// The format of access log lines.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    /// Common Log Format: `host ident user [time] "request" status size`.
+    Common,
+    /// Combined Log Format, which is Common with `"referer" "user-agent"`.
+    Combined,
+    /// A JSON object per line, with the latency and the request ID too.
+    Json,
+}
+
+/// RotatingFile is a log file renamed to `<path>.1`, `<path>.2`, ... when it gets larger than `max_bytes`.
+/// Files older than `<path>.<max_files>` are removed.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    /// The file and its size.
+    state: Mutex<(File, u64)>,
+}
+
+impl RotatingFile {
+    pub fn open<P: AsRef<Path>>(path: P, max_bytes: u64, max_files: usize) -> io::Result<RotatingFile> {
+        let path = path.as_ref().to_path_buf();
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile {
+            path: path,
+            max_bytes: max_bytes,
+            max_files: max_files,
+            state: Mutex::new((file, size)),
+        })
+    }
+
+    fn rotated_path(&self, n: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", n));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&self) -> io::Result<File> {
+        if self.max_files == 0 {
+            return OpenOptions::new().create(true).write(true).truncate(true).open(&self.path);
+        }
+
+        let oldest = self.rotated_path(self.max_files);
+        if oldest.exists() {
+            fs::remove_file(&oldest)?;
+        }
+        for n in (1..self.max_files).rev() {
+            let from = self.rotated_path(n);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+        OpenOptions::new().create(true).append(true).open(&self.path)
+    }
+
+    pub fn write_line(&self, line: &str) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.1 > 0 && state.1 + line.len() as u64 + 1 > self.max_bytes {
+            state.0 = self.rotate()?;
+            state.1 = 0;
+        }
+
+        writeln!(state.0, "{}", line)?;
+        state.1 += line.len() as u64 + 1;
+        Ok(())
+    }
+}
+
+enum Output {
+    /// `info!` with the target `zircon::access`.
+    Log,
+    File(RotatingFile),
+}
+
+impl Output {
+    fn write(&self, line: &str) {
+        match *self {
+            Output::Log => info!(target: "zircon::access", "{}", line),
+            Output::File(ref file) => {
+                if let Err(err) = file.write_line(line) {
+                    error!("failed to write access log: {}", err);
+                }
+            },
+        }
+    }
+}
+
+/// Returns (year, month, day, hour, minute, second) in UTC.
+fn utc(time: SystemTime) -> (i64, u32, u32, u32, u32, u32) {
+    let secs = time.duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0) as i64;
+    let (days, rest) = (secs / 86400, (secs % 86400) as u32);
+
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day, rest / 3600, rest / 60 % 60, rest % 60)
+}
+
+const MONTHS: [&'static str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun",
+                                    "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// Entry is what is logged for a request.
+struct Entry {
+    time: SystemTime,
+    remote_addr: Option<IpAddr>,
+    method: Method,
+    /// The path with the query string.
+    uri: String,
+    version: HttpVersion,
+    status: u16,
+    size: Option<u64>,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    request_id: String,
+    latency: Duration,
+}
+
+/// Quotes `s` for Common and Combined Log Format.
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl Entry {
+    fn common(&self) -> String {
+        let (year, month, day, hour, min, sec) = utc(self.time);
+        format!("{} - - [{:02}/{}/{}:{:02}:{:02}:{:02} +0000] {} {} {}",
+                self.remote_addr.map_or("-".to_string(), |x| x.to_string()),
+                day, MONTHS[(month - 1) as usize], year, hour, min, sec,
+                quote(&format!("{} {} {}", self.method, self.uri, self.version)),
+                self.status,
+                self.size.map_or("-".to_string(), |x| x.to_string()))
+    }
+
+    fn combined(&self) -> String {
+        format!("{} {} {}", self.common(),
+                quote(self.referer.as_ref().map_or("-", |x| x.as_str())),
+                quote(self.user_agent.as_ref().map_or("-", |x| x.as_str())))
+    }
+
+    fn json(&self) -> String {
+        let (year, month, day, hour, min, sec) = utc(self.time);
+        let string_or_null = |x: Option<&String>| x.map_or(Json::Null, |x| Json::String(x.clone()));
+        let latency_ms = self.latency.as_secs() as f64 * 1e3 + self.latency.subsec_nanos() as f64 / 1e6;
+
+        let mut obj = Map::new();
+        obj.insert("time".to_string(), Json::String(format!("{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+                                                             year, month, day, hour, min, sec)));
+        obj.insert("remote_addr".to_string(), string_or_null(self.remote_addr.map(|x| x.to_string()).as_ref()));
+        obj.insert("method".to_string(), Json::String(self.method.to_string()));
+        obj.insert("uri".to_string(), Json::String(self.uri.clone()));
+        obj.insert("version".to_string(), Json::String(self.version.to_string()));
+        obj.insert("status".to_string(), Json::from(self.status));
+        obj.insert("size".to_string(), self.size.map_or(Json::Null, Json::from));
+        obj.insert("referer".to_string(), string_or_null(self.referer.as_ref()));
+        obj.insert("user_agent".to_string(), string_or_null(self.user_agent.as_ref()));
+        obj.insert("request_id".to_string(), Json::String(self.request_id.clone()));
+        obj.insert("latency_ms".to_string(), Json::from(latency_ms));
+        Json::Object(obj).to_string()
+    }
+
+    fn format(&self, format: LogFormat) -> String {
+        match format {
+            LogFormat::Common => self.common(),
+            LogFormat::Combined => self.combined(),
+            LogFormat::Json => self.json(),
+        }
+    }
+}
+
+/// AccessLog logs a line per request. Set it by `ZirconConfig::with_access_log`.
+///
+/// It is not a middleware, so that the line has what is actually sent: the response made by
+/// the ErrorHandler when the handler failed or panicked, and the 404 and 405 responses of a Router.
+/// The size is Content-Length, or the number of bytes of a streamed body, in which case the line is
+/// written when the body is finished and the latency includes sending the body.
+///
+/// The remote address is `Request::remote_addr()`, so it is the client behind trusted proxies.
+/// The request ID is taken from X-Request-Id, or made if the request doesn't have a valid one:
+/// at most 128 characters of letters, digits and `-_.:+/=`. It is set to the request for handlers,
+/// and to the response.
+///
+/// ```ignore
+/// let config = ZirconConfig::prod()
+///     .with_access_log(Some(AccessLog::new()
+///                           .with_format(LogFormat::Json)
+///                           .with_file(RotatingFile::open("access.log", 100 << 20, 5)?)
+///                           .exclude("/healthz")));
+/// ```
+#[derive(Clone)]
+pub struct AccessLog {
+    format: LogFormat,
+    output: Arc<Output>,
+    excluded: Vec<String>,
+}
+
+impl AccessLog {
+    /// Logs in Combined Log Format through the `log` crate, with the target `zircon::access`.
+    pub fn new() -> AccessLog {
+        AccessLog {
+            format: LogFormat::Combined,
+            output: Arc::new(Output::Log),
+            excluded: Vec::new(),
+        }
+    }
+
+    pub fn with_format(mut self, format: LogFormat) -> AccessLog {
+        self.format = format;
+        self
+    }
+
+    /// Writes to `file` instead of the `log` crate.
+    pub fn with_file(mut self, file: RotatingFile) -> AccessLog {
+        self.output = Arc::new(Output::File(file));
+        self
+    }
+
+    /// Doesn't log the requests for `path`, e.g. health checks. The query string is not compared.
+    pub fn exclude(mut self, path: &str) -> AccessLog {
+        self.excluded.push(path.to_string());
+        self
+    }
+}
+
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// Returns true if `id` can be logged as is. Anything else could forge log lines or fields.
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN &&
+        id.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.:+/=".contains(&b))
+}
+
+/// Pending is the log line of a request being served.
+pub struct Pending {
+    entry: Entry,
+    start: Instant,
+    format: LogFormat,
+    output: Arc<Output>,
+}
+
+/// Starts logging `req`. None is returned if it is excluded.
+pub fn start(log: &AccessLog, req: &mut Request) -> Option<Pending> {
+    if log.excluded.iter().any(|x| x == req.uri().path()) {
+        return None;
+    }
+
+    let request_id = match req.headers().get::<XRequestId>() {
+        Some(x) if is_valid_request_id(&x.0) => Some(x.0.clone()),
+        _ => None,
+    };
+    let request_id = match request_id {
+        Some(id) => id,
+        None => {
+            let id = new_request_id();
+            req.headers_mut().set(XRequestId(id.clone()));
+            id
+        },
+    };
+
+    let entry = Entry {
+        time: SystemTime::now(),
+        remote_addr: req.remote_addr(),
+        method: req.method().clone(),
+        uri: match req.uri().query() {
+            Some(query) => format!("{}?{}", req.uri().path(), query),
+            None => req.uri().path().to_string(),
+        },
+        version: req.version(),
+        status: 0,
+        size: None,
+        referer: req.headers().get::<Referer>().map(|x| x.to_string()),
+        user_agent: req.headers().get::<UserAgent>().map(|x| x.to_string()),
+        request_id: request_id,
+        latency: Duration::from_secs(0),
+    };
+
+    Some(Pending {
+        entry: entry,
+        start: Instant::now(),
+        format: log.format,
+        output: log.output.clone(),
+    })
+}
+
+impl Pending {
+    /// Logs `resp`, which is the response sent to the client.
+    /// A streamed body is replaced with one counting its bytes on `handle`.
+    pub fn finish(mut self, mut resp: HyperResponse, handle: &Handle) -> HyperResponse {
+        resp.headers_mut().set(XRequestId(self.entry.request_id.clone()));
+        self.entry.status = resp.status().as_u16();
+        self.entry.size = resp.headers().get::<ContentLength>().map(|x| x.0);
+
+        // HEAD has no body even if the handler set one.
+        if self.entry.size.is_some() || self.entry.method == Method::Head || resp.body_ref().is_none() {
+            self.write();
+            return resp;
+        }
+
+        let status = resp.status();
+        let headers = mem::replace(resp.headers_mut(), Headers::new());
+        let (tx, body) = Body::pair();
+        let size = Rc::new(Cell::new(0));
+        let counter = size.clone();
+        let chunks = resp.body().then(move |chunk| {
+            if let Ok(ref chunk) = chunk {
+                counter.set(counter.get() + chunk.len() as u64);
+            }
+            Ok::<_, SendError<Result<Chunk, hyper::Error>>>(chunk)
+        });
+        handle.spawn(tx.send_all(chunks).then(move |_| {
+            self.entry.size = Some(size.get());
+            self.write();
+            Ok::<(), ()>(())
+        }));
+
+        HyperResponse::new().with_status(status).with_headers(headers).with_body(body)
+    }
+
+    fn write(mut self) {
+        self.entry.latency = self.start.elapsed();
+        self.output.write(&self.entry.format(self.format));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::io::Read;
+    use std::net::Ipv4Addr;
+
+    fn entry() -> Entry {
+        Entry {
+            time: UNIX_EPOCH + Duration::from_secs(971211336),
+            remote_addr: Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))),
+            method: Method::Get,
+            uri: "/index.html?q=1".to_string(),
+            version: HttpVersion::Http11,
+            status: 200,
+            size: Some(2326),
+            referer: None,
+            user_agent: Some("curl/7.0 \"x\"".to_string()),
+            request_id: "abc".to_string(),
+            latency: Duration::from_millis(12),
+        }
+    }
+
+    #[test]
+    fn utc_time() {
+        assert_eq!(utc(UNIX_EPOCH), (1970, 1, 1, 0, 0, 0));
+        assert_eq!(utc(UNIX_EPOCH + Duration::from_secs(971211336)), (2000, 10, 10, 20, 55, 36));
+        assert_eq!(utc(UNIX_EPOCH + Duration::from_secs(951782400)), (2000, 2, 29, 0, 0, 0));
+    }
+
+    #[test]
+    fn formats() {
+        let e = entry();
+        assert_eq!(e.format(LogFormat::Common),
+                   r#"192.0.2.1 - - [10/Oct/2000:20:55:36 +0000] "GET /index.html?q=1 HTTP/1.1" 200 2326"#);
+        assert_eq!(e.format(LogFormat::Combined),
+                   concat!(r#"192.0.2.1 - - [10/Oct/2000:20:55:36 +0000] "GET /index.html?q=1 HTTP/1.1" 200 2326"#,
+                           r#" "-" "curl/7.0 \"x\"""#));
+
+        let json: Json = ::serde_json::from_str(&e.format(LogFormat::Json)).unwrap();
+        assert_eq!(json["time"], "2000-10-10T20:55:36Z");
+        assert_eq!(json["method"], "GET");
+        assert_eq!(json["status"], 200);
+        assert_eq!(json["referer"], Json::Null);
+        assert_eq!(json["request_id"], "abc");
+        assert_eq!(json["latency_ms"], 12.0);
+    }
+
+    #[test]
+    fn request_id() {
+        assert!(is_valid_request_id("f81d4fae-7dec-11d0-a765-00a0c91e6bf6"));
+        assert!(is_valid_request_id("trace:abc/1+2=="));
+        assert!(!is_valid_request_id(""));
+        assert!(!is_valid_request_id("abc\" \"injected"));
+        assert!(!is_valid_request_id("abc\u{1b}[31m"));
+        assert!(!is_valid_request_id(&"a".repeat(MAX_REQUEST_ID_LEN + 1)));
+    }
+
+    #[test]
+    fn rotating_file() {
+        let path = env::temp_dir().join(format!("zircon-access-{}.log", new_request_id()));
+        let file = RotatingFile::open(&path, 10, 2).unwrap();
+        for line in &["aaaa", "bbbb", "cccc", "dddd", "eeee"] {
+            file.write_line(line).unwrap();
+        }
+
+        let read = |path: &Path| {
+            let mut s = String::new();
+            File::open(path).unwrap().read_to_string(&mut s).unwrap();
+            s
+        };
+        assert_eq!(read(&path), "eeee\n");
+        assert_eq!(read(&file.rotated_path(1)), "cccc\ndddd\n");
+        assert_eq!(read(&file.rotated_path(2)), "aaaa\nbbbb\n");
+        assert!(!file.rotated_path(3).exists());
+
+        for n in 0..3 {
+            let _ = fs::remove_file(if n == 0 { path.clone() } else { file.rotated_path(n) });
+        }
+    }
+}
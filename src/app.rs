@@ -1,13 +1,27 @@
+use std::borrow::Cow;
+
 use prelude::*;
 use futures_cpupool::CpuPool;
+use handlers::router::UrlMap;
 
 pub trait ZirconApp : Send + Sync + 'static {
+    /// Returns the server configuration. The server reads every setting from here, for each connection
+    /// and request, so an app keeping its own `ZirconConfig` should return it.
+    /// The default is `ZirconConfig::prod()` with the thread counts of this app.
+    fn config(&self) -> Cow<ZirconConfig> {
+        Cow::Owned(ZirconConfig::prod().with_num_accept_threads(self.num_accept_threads())
+                                       .with_num_cpu_threads(self.num_cpu_threads()))
+    }
     /// Returns the number of accept threads.
     fn num_accept_threads(&self) -> usize;
     /// Returns the number of cpu threads.
     fn num_cpu_threads(&self) -> usize;
     /// Returns the internal cpu pool (not for accept threads).
     fn cpu_pool(&self) -> &CpuPool;
+    /// Returns the named routes, so that handlers can make URLs by `url_for`.
+    fn url_map(&self) -> Option<&UrlMap> {
+        None
+    }
 }
 
 #[derive(Clone)]
@@ -15,6 +29,7 @@ pub struct ZirconDefaultApp<D: Send + Sync + 'static> {
     pub config: ZirconConfig,
     pub cpu_pool: CpuPool,
     pub server_data: D,
+    url_map: Option<UrlMap>,
 }
 
 impl<D: Send + Sync + 'static> ZirconDefaultApp<D> {
@@ -24,6 +39,7 @@ impl<D: Send + Sync + 'static> ZirconDefaultApp<D> {
             config: config,
             cpu_pool: CpuPool::new(cpu_threads),
             server_data: (),
+            url_map: None,
         }
     }
 
@@ -32,11 +48,22 @@ impl<D: Send + Sync + 'static> ZirconDefaultApp<D> {
             config: self.config,
             cpu_pool: self.cpu_pool,
             server_data: data,
+            url_map: self.url_map,
         }
     }
+
+    /// Sets the named routes. Take them by `Router::url_map()` after adding every route.
+    pub fn with_url_map(mut self, url_map: UrlMap) -> ZirconDefaultApp<D> {
+        self.url_map = Some(url_map);
+        self
+    }
 }
 
 impl<D: Send + Sync + 'static> ZirconApp for ZirconDefaultApp<D> {
+    fn config(&self) -> Cow<ZirconConfig> {
+        Cow::Borrowed(&self.config)
+    }
+
     fn num_accept_threads(&self) -> usize {
         self.config.num_accept_threads()
     }
@@ -48,4 +75,8 @@ impl<D: Send + Sync + 'static> ZirconApp for ZirconDefaultApp<D> {
     fn cpu_pool(&self) -> &CpuPool {
         &self.cpu_pool
     }
+
+    fn url_map(&self) -> Option<&UrlMap> {
+        self.url_map.as_ref()
+    }
 }
@@ -1,4 +1,10 @@
 use std;
+use std::sync::Arc;
+use std::time::Duration;
+
+use access_log::AccessLog;
+use hyper::StatusCode;
+use request::{Cidr, ForwardedHeaders};
 
 #[derive(Clone, Copy, PartialEq)]
 pub enum Mode {
@@ -33,8 +39,21 @@ impl Mode {
 pub struct ZirconConfig {
     mode: Mode,
     respect_xforwarded: bool,
+    trusted_proxies: Arc<Vec<Cidr>>,
+    forwarded_headers: ForwardedHeaders,
     num_accept_threads: usize,
     num_cpu_threads: usize,
+    shutdown_timeout: Duration,
+    header_read_timeout: Option<Duration>,
+    keep_alive_timeout: Option<Duration>,
+    body_read_timeout: Option<Duration>,
+    handler_timeout: Option<Duration>,
+    handler_timeout_status: StatusCode,
+    max_connections_per_thread: Option<usize>,
+    listen_backlog: i32,
+    upgrade_on_signal: bool,
+    upgrade_timeout: Duration,
+    access_log: Option<AccessLog>,
 }
 
 impl ZirconConfig {
@@ -60,22 +79,55 @@ impl ZirconConfig {
             Mode::Prod => 16,
         };
 
+        let loopback = vec![
+            "127.0.0.0/8".parse().unwrap(),
+            "::1".parse().unwrap(),
+        ];
+
         ZirconConfig {
             mode: mode,
             respect_xforwarded: false,
+            trusted_proxies: Arc::new(loopback),
+            forwarded_headers: ForwardedHeaders::XForwarded,
             num_accept_threads: num_accept_threads,
             num_cpu_threads: num_cpu_threads,
+            shutdown_timeout: Duration::from_secs(30),
+            header_read_timeout: Some(Duration::from_secs(30)),
+            keep_alive_timeout: Some(Duration::from_secs(60)),
+            body_read_timeout: Some(Duration::from_secs(30)),
+            handler_timeout: None,
+            handler_timeout_status: StatusCode::ServiceUnavailable,
+            max_connections_per_thread: None,
+            listen_backlog: 128,
+            upgrade_on_signal: false,
+            upgrade_timeout: Duration::from_secs(30),
+            access_log: None,
         }
     }
 
-    /// When `respect_xforwarded` is true, Request will respect X-Forwarded-*.
-    /// host(), port(), scheme(), and remote_addr() will respect X-Forwarded-*.
+    /// When `respect_xforwarded` is true, Request will respect Forwarded (RFC 7239) and X-Forwarded-*.
+    /// host(), port(), scheme(), and remote_addr() will respect them.
     /// It will be useful when an application is running behind a reverse proxy.
+    /// They are respected only when the peer is one of `trusted_proxies`.
     pub fn with_respect_xforwarded(mut self, b: bool) -> ZirconConfig {
         self.respect_xforwarded = b;
         self
     }
 
+    /// The reverse proxies whose Forwarded and X-Forwarded-* are respected. The default is loopback addresses.
+    /// Peers on a Unix domain socket are always trusted.
+    pub fn with_trusted_proxies(mut self, proxies: Vec<Cidr>) -> ZirconConfig {
+        self.trusted_proxies = Arc::new(proxies);
+        self
+    }
+
+    /// Which of Forwarded and X-Forwarded-* from trusted proxies is respected. The other one is ignored,
+    /// even when the chosen one is missing. The default is X-Forwarded-*.
+    pub fn with_forwarded_headers(mut self, headers: ForwardedHeaders) -> ZirconConfig {
+        self.forwarded_headers = headers;
+        self
+    }
+
     pub fn with_num_accept_threads(mut self, n: usize) -> ZirconConfig {
         self.num_accept_threads = n;
         self
@@ -86,11 +138,98 @@ impl ZirconConfig {
         self
     }
 
+    /// On shutdown, requests in flight are given this much time to finish.
+    /// Requests still running after the timeout are dropped.
+    pub fn with_shutdown_timeout(mut self, timeout: Duration) -> ZirconConfig {
+        self.shutdown_timeout = timeout;
+        self
+    }
+
+    /// A new connection is closed if it doesn't send request headers within this time.
+    /// None means no timeout.
+    pub fn with_header_read_timeout(mut self, timeout: Option<Duration>) -> ZirconConfig {
+        self.header_read_timeout = timeout;
+        self
+    }
+
+    /// A keep-alive connection is closed if it doesn't send the next request within this time.
+    /// None means no timeout.
+    pub fn with_keep_alive_timeout(mut self, timeout: Option<Duration>) -> ZirconConfig {
+        self.keep_alive_timeout = timeout;
+        self
+    }
+
+    /// While a handler reads the request body, reading fails if the client sends nothing within this time.
+    /// None means no timeout.
+    pub fn with_body_read_timeout(mut self, timeout: Option<Duration>) -> ZirconConfig {
+        self.body_read_timeout = timeout;
+        self
+    }
+
+    /// When a handler doesn't finish within this time, the request fails with `handler_timeout_status`,
+    /// which is passed to the error handler. None (default) means no timeout.
+    /// Only the future returned from the handler is timed; the handler itself must not block.
+    pub fn with_handler_timeout(mut self, timeout: Option<Duration>) -> ZirconConfig {
+        self.handler_timeout = timeout;
+        self
+    }
+
+    /// The status of a request whose handler timed out. The default is 503 Service Unavailable.
+    /// 504 Gateway Timeout suits a handler waiting for an upstream server.
+    pub fn with_handler_timeout_status(mut self, status: StatusCode) -> ZirconConfig {
+        self.handler_timeout_status = status;
+        self
+    }
+
+    /// Each accept thread closes new connections immediately while it already has this many.
+    /// None (default) means no limit.
+    pub fn with_max_connections_per_thread(mut self, n: Option<usize>) -> ZirconConfig {
+        self.max_connections_per_thread = n;
+        self
+    }
+
+    /// The backlog of TCP listening sockets.
+    pub fn with_listen_backlog(mut self, backlog: i32) -> ZirconConfig {
+        self.listen_backlog = backlog;
+        self
+    }
+
+    /// When true, SIGUSR2 replaces the running server with a new process of the same executable.
+    /// See `UpgradeHandle`.
+    pub fn with_upgrade_on_signal(mut self, b: bool) -> ZirconConfig {
+        self.upgrade_on_signal = b;
+        self
+    }
+
+    /// On upgrade, the new process is killed if it doesn't get ready within this time.
+    pub fn with_upgrade_timeout(mut self, timeout: Duration) -> ZirconConfig {
+        self.upgrade_timeout = timeout;
+        self
+    }
+
+    /// Logs a line per request. None (default) means no access log.
+    pub fn with_access_log(mut self, log: Option<AccessLog>) -> ZirconConfig {
+        self.access_log = log;
+        self
+    }
+
     /// Server mode of the current configuration
     pub fn mode(&self) -> Mode {
         self.mode
     }
 
+    pub fn respect_xforwarded(&self) -> bool {
+        self.respect_xforwarded
+    }
+
+    pub fn trusted_proxies(&self) -> &Arc<Vec<Cidr>> {
+        &self.trusted_proxies
+    }
+
+    pub fn forwarded_headers(&self) -> ForwardedHeaders {
+        self.forwarded_headers
+    }
+
     pub fn num_accept_threads(&self) -> usize {
         self.num_accept_threads
     }
@@ -98,4 +237,48 @@ impl ZirconConfig {
     pub fn num_cpu_threads(&self) -> usize {
         self.num_cpu_threads
     }
+
+    pub fn shutdown_timeout(&self) -> Duration {
+        self.shutdown_timeout
+    }
+
+    pub fn header_read_timeout(&self) -> Option<Duration> {
+        self.header_read_timeout
+    }
+
+    pub fn keep_alive_timeout(&self) -> Option<Duration> {
+        self.keep_alive_timeout
+    }
+
+    pub fn body_read_timeout(&self) -> Option<Duration> {
+        self.body_read_timeout
+    }
+
+    pub fn handler_timeout(&self) -> Option<Duration> {
+        self.handler_timeout
+    }
+
+    pub fn handler_timeout_status(&self) -> StatusCode {
+        self.handler_timeout_status
+    }
+
+    pub fn max_connections_per_thread(&self) -> Option<usize> {
+        self.max_connections_per_thread
+    }
+
+    pub fn listen_backlog(&self) -> i32 {
+        self.listen_backlog
+    }
+
+    pub fn upgrade_on_signal(&self) -> bool {
+        self.upgrade_on_signal
+    }
+
+    pub fn upgrade_timeout(&self) -> Duration {
+        self.upgrade_timeout
+    }
+
+    pub fn access_log(&self) -> Option<&AccessLog> {
+        self.access_log.as_ref()
+    }
 }
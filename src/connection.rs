@@ -0,0 +1,271 @@
+use std::cell::Cell;
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll, Stream};
+use hyper;
+use tokio_core::reactor::{Handle, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+/// Activity tracks whether a connection is serving a request or waiting for the next one.
+pub struct Activity {
+    requests: Cell<usize>,
+    idle_since: Cell<Instant>,
+    served: Cell<bool>,
+}
+
+impl Activity {
+    pub fn new() -> Activity {
+        Activity {
+            requests: Cell::new(0),
+            idle_since: Cell::new(Instant::now()),
+            served: Cell::new(false),
+        }
+    }
+
+    pub fn start_request(&self) {
+        self.requests.set(self.requests.get() + 1);
+    }
+
+    pub fn finish_request(&self) {
+        self.requests.set(self.requests.get() - 1);
+        self.idle_since.set(Instant::now());
+        self.served.set(true);
+    }
+
+    /// Records that a part of a response was written. The response body is written after the request
+    /// is finished, so the idle time is counted from the last write.
+    fn wrote(&self) {
+        self.idle_since.set(Instant::now());
+    }
+
+    /// Returns when the connection should be closed if no request comes.
+    /// None is returned while a request is being served, or when no timeout applies.
+    fn deadline(&self, header_read_timeout: Option<Duration>, keep_alive_timeout: Option<Duration>) -> Option<Instant> {
+        if self.requests.get() > 0 {
+            return None;
+        }
+
+        let timeout = if self.served.get() { keep_alive_timeout } else { header_read_timeout };
+        timeout.map(|t| self.idle_since.get() + t)
+    }
+}
+
+/// ActivityIo wraps a connection to record its writes in Activity.
+pub struct ActivityIo<I> {
+    io: I,
+    activity: Rc<Activity>,
+}
+
+impl<I> ActivityIo<I> {
+    pub fn new(io: I, activity: Rc<Activity>) -> ActivityIo<I> {
+        ActivityIo {
+            io: io,
+            activity: activity,
+        }
+    }
+}
+
+impl<I: Read> Read for ActivityIo<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.io.read(buf)
+    }
+}
+
+impl<I: Write> Write for ActivityIo<I> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.io.write(buf)?;
+        self.activity.wrote();
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+impl<I: AsyncRead> AsyncRead for ActivityIo<I> {}
+
+impl<I: AsyncWrite> AsyncWrite for ActivityIo<I> {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.io.shutdown()
+    }
+}
+
+/// IdleWatchdog resolves when a connection has waited too long for a request.
+///
+/// Before the first request, the connection is given `header_read_timeout` to send its headers.
+/// After that, it is given `keep_alive_timeout` to send the next request, counted from the last write
+/// of the response when the connection is wrapped with ActivityIo. So a slow client reading a large body
+/// is not cut off as long as the body is making progress.
+pub struct IdleWatchdog {
+    timer: Timeout,
+    activity: Rc<Activity>,
+    header_read_timeout: Option<Duration>,
+    keep_alive_timeout: Option<Duration>,
+}
+
+impl IdleWatchdog {
+    /// Returns None if neither timeout is set.
+    pub fn new(activity: Rc<Activity>, header_read_timeout: Option<Duration>, keep_alive_timeout: Option<Duration>,
+               handle: &Handle) -> io::Result<Option<IdleWatchdog>> {
+        let first = match header_read_timeout.or(keep_alive_timeout) {
+            Some(x) => x,
+            None => return Ok(None),
+        };
+
+        Ok(Some(IdleWatchdog {
+            timer: Timeout::new(first, handle)?,
+            activity: activity,
+            header_read_timeout: header_read_timeout,
+            keep_alive_timeout: keep_alive_timeout,
+        }))
+    }
+
+    /// While a request is being served, the watchdog wakes up this often to check the connection again.
+    fn recheck_interval(&self) -> Duration {
+        match (self.header_read_timeout, self.keep_alive_timeout) {
+            (Some(a), Some(b)) => cmp::min(a, b),
+            (Some(a), None) | (None, Some(a)) => a,
+            (None, None) => unreachable!(),
+        }
+    }
+}
+
+impl Future for IdleWatchdog {
+    type Item = ();
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<(), io::Error> {
+        loop {
+            match self.timer.poll()? {
+                Async::NotReady => return Ok(Async::NotReady),
+                Async::Ready(()) => (),
+            }
+
+            let now = Instant::now();
+            match self.activity.deadline(self.header_read_timeout, self.keep_alive_timeout) {
+                Some(deadline) if deadline <= now => return Ok(Async::Ready(())),
+                Some(deadline) => self.timer.reset(deadline),
+                None => {
+                    let next = now + self.recheck_interval();
+                    self.timer.reset(next)
+                },
+            }
+        }
+    }
+}
+
+/// ReadTimeout fails a request body when the client sends nothing for `timeout` while the body is read.
+///
+/// The time is counted only while the body is polled, so a handler that is slow to consume the body
+/// doesn't make the client time out.
+pub struct ReadTimeout<S> {
+    stream: S,
+    timeout: Duration,
+    timer: Timeout,
+    waiting: bool,
+}
+
+impl<S> ReadTimeout<S> {
+    /// `timer` is reset whenever the body starts to be waited for.
+    pub fn new(stream: S, timeout: Duration, timer: Timeout) -> ReadTimeout<S> {
+        ReadTimeout {
+            stream: stream,
+            timeout: timeout,
+            timer: timer,
+            waiting: false,
+        }
+    }
+}
+
+impl<S: Stream<Error = hyper::Error>> Stream for ReadTimeout<S> {
+    type Item = S::Item;
+    type Error = hyper::Error;
+
+    fn poll(&mut self) -> Poll<Option<S::Item>, hyper::Error> {
+        if !self.waiting {
+            self.timer.reset(Instant::now() + self.timeout);
+            self.waiting = true;
+        }
+
+        if let Async::Ready(x) = self.stream.poll()? {
+            self.waiting = false;
+            return Ok(Async::Ready(x));
+        }
+
+        match self.timer.poll()? {
+            Async::Ready(()) => Err(io::Error::new(io::ErrorKind::TimedOut, "request body read timed out").into()),
+            Async::NotReady => Ok(Async::NotReady),
+        }
+    }
+}
+
+/// ConnectionLimit counts the connections of one accept thread.
+#[derive(Clone)]
+pub struct ConnectionLimit {
+    count: Rc<Cell<usize>>,
+    max: Option<usize>,
+}
+
+impl ConnectionLimit {
+    pub fn new(max: Option<usize>) -> ConnectionLimit {
+        ConnectionLimit {
+            count: Rc::new(Cell::new(0)),
+            max: max,
+        }
+    }
+
+    /// Returns a guard holding a slot until it is dropped.
+    /// None is returned when the limit is reached.
+    pub fn acquire(&self) -> Option<ConnectionGuard> {
+        if let Some(max) = self.max {
+            if self.count.get() >= max {
+                return None;
+            }
+        }
+
+        self.count.set(self.count.get() + 1);
+        Some(ConnectionGuard(self.count.clone()))
+    }
+
+    pub fn count(&self) -> usize {
+        self.count.get()
+    }
+}
+
+pub struct ConnectionGuard(Rc<Cell<usize>>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() - 1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Sink;
+    use hyper::Body;
+    use tokio_core::reactor::Core;
+
+    #[test]
+    fn read_timeout() {
+        let mut core = Core::new().unwrap();
+        let timeout = Duration::from_millis(50);
+
+        let (tx, body) = Body::pair();
+        let tx = tx.send(Ok("a".into())).wait().unwrap();
+        drop(tx);
+        let body = ReadTimeout::new(body, timeout, Timeout::new(timeout, &core.handle()).unwrap());
+        assert_eq!(core.run(body.concat2()).unwrap().as_ref(), b"a");
+
+        // The client stops sending in the middle of the body.
+        let (tx, body) = Body::pair();
+        let _tx = tx.send(Ok("a".into())).wait().unwrap();
+        let body = ReadTimeout::new(body, timeout, Timeout::new(timeout, &core.handle()).unwrap());
+        assert!(core.run(body.concat2()).is_err());
+    }
+}
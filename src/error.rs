@@ -2,7 +2,8 @@ use std;
 use std::error::Error;
 
 use futures::{Future, IntoFuture};
-use hyper;
+use hyper::header::Allow;
+use hyper::{self, Method};
 use prelude::*;
 use serde_json;
 
@@ -11,9 +12,17 @@ pub enum ZirconError {
     Status(StatusCode),
     /// An error that specifies http status and error message.
     StringError(StatusCode, String),
+    /// 405 Method Not Allowed, with the methods allowed for the path.
+    MethodNotAllowed(Vec<Method>),
     IoError(std::io::Error),
     HyperError(hyper::Error),
     JsonError(serde_json::Error),
+    /// The handler panicked. It has the panic message and the location.
+    Panic(String),
+    /// Not to be used. Matching on ZirconError needs a wildcard arm because of this,
+    /// so that adding a variant is not a breaking change.
+    #[doc(hidden)]
+    __Nonexhaustive,
 }
 
 impl ZirconError {
@@ -25,6 +34,18 @@ impl ZirconError {
         ZirconError::StringError(code, message.into())
     }
 
+    /// Returns the status of the response made by the default error handler.
+    pub fn status(&self) -> StatusCode {
+        match *self {
+            ZirconError::Status(code) | ZirconError::StringError(code, _) => code,
+            ZirconError::MethodNotAllowed(_) => StatusCode::MethodNotAllowed,
+            ZirconError::IoError(_) | ZirconError::HyperError(_) | ZirconError::JsonError(_) |
+            ZirconError::Panic(_) | ZirconError::__Nonexhaustive => {
+                StatusCode::InternalServerError
+            },
+        }
+    }
+
     pub fn render_error_status(code: StatusCode) -> HandlerResult {
         Err(ZirconError::Status(code)).into_future().boxed()
     }
@@ -62,6 +83,10 @@ pub fn make_default_error_response(err: &ZirconError) -> Response {
             resp.origin.set_status(code);
             resp.origin.set_body(message.to_string());
         },
+        &ZirconError::MethodNotAllowed(ref methods) => {
+            resp.origin.set_status(StatusCode::MethodNotAllowed);
+            resp.origin.headers_mut().set(Allow(methods.clone()));
+        },
         &ZirconError::IoError(ref io_err) => {
             resp.origin.set_status(StatusCode::InternalServerError);
             resp.origin.set_body(io_err.description().to_string());
@@ -73,7 +98,14 @@ pub fn make_default_error_response(err: &ZirconError) -> Response {
         &ZirconError::JsonError(ref json_err) => {
             resp.origin.set_status(StatusCode::InternalServerError);
             resp.origin.set_body(json_err.description().to_string());
-        }
+        },
+        &ZirconError::Panic(_) => {
+            // The message is only logged, not to leak the internals.
+            resp.origin.set_status(StatusCode::InternalServerError);
+        },
+        &ZirconError::__Nonexhaustive => {
+            resp.origin.set_status(StatusCode::InternalServerError);
+        },
     }
 
     resp
@@ -2,8 +2,10 @@ pub mod mount_handler;
 pub mod router;
 pub mod single_file_handler;
 pub mod static_file_handler;
+pub mod virtual_host_handler;
 
 pub use self::mount_handler::MountHandler;
 pub use self::router::Router;
 pub use self::single_file_handler::SingleFileHandler;
 pub use self::static_file_handler::StaticFileHandler;
+pub use self::virtual_host_handler::VirtualHostHandler;
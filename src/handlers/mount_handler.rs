@@ -29,10 +29,12 @@ impl<A: ZirconApp, H: Handler<A>> Handler<A> for MountHandler<A, H> {
         // So, can't we use &str instead of String?
         let new_path = {
             let path = req.path();
-            if !path.starts_with(&self.mount_point) {
-                panic!("path didn't match");
+            let rest = if path.starts_with(&self.mount_point) { &path[self.mount_point.len()..] } else { "" };
+            // "/foo" must not match "/foobar".
+            if !path.starts_with(&self.mount_point) || !(rest.is_empty() || rest.starts_with('/')) {
+                return ZirconError::render_error_status(StatusCode::NotFound);
             }
-            path[self.mount_point.len()..].to_string()
+            rest.to_string()
         };
 
         req.set_modified_path(Some(new_path));
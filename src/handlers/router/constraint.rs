@@ -0,0 +1,318 @@
+use std::ascii::AsciiExt;
+
+use regex::Regex;
+
+/// The types a route param can be constrained to, like `:id<u64>` or `{id:int}`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ParamType {
+    I8, I16, I32, I64,
+    U8, U16, U32, U64,
+    F32, F64,
+}
+
+impl ParamType {
+    fn from_name(name: &str) -> Option<ParamType> {
+        let t = match name {
+            "i8" => ParamType::I8,
+            "i16" => ParamType::I16,
+            "i32" => ParamType::I32,
+            "i64" | "int" => ParamType::I64,
+            "u8" => ParamType::U8,
+            "u16" => ParamType::U16,
+            "u32" => ParamType::U32,
+            "u64" | "uint" => ParamType::U64,
+            "f32" => ParamType::F32,
+            "f64" | "float" => ParamType::F64,
+            _ => return None,
+        };
+        Some(t)
+    }
+
+    fn is_signed(&self) -> bool {
+        match *self {
+            ParamType::I8 | ParamType::I16 | ParamType::I32 | ParamType::I64 |
+            ParamType::F32 | ParamType::F64 => true,
+            _ => false,
+        }
+    }
+
+    fn is_float(&self) -> bool {
+        *self == ParamType::F32 || *self == ParamType::F64
+    }
+
+    /// The regex of the text form. The range is not checked by this.
+    fn regex(&self) -> &'static str {
+        if self.is_float() {
+            r"-?[0-9]+(?:\.[0-9]+)?"
+        } else if self.is_signed() {
+            "-?[0-9]+"
+        } else {
+            "[0-9]+"
+        }
+    }
+
+    fn matches(&self, s: &str) -> bool {
+        let digits = if self.is_signed() && s.starts_with('-') { &s[1..] } else { s };
+        let mut parts = digits.splitn(2, '.');
+        let int_part = parts.next().unwrap_or("");
+        let frac_part = parts.next();
+        if int_part.is_empty() || !int_part.bytes().all(|b| b.is_ascii_digit()) {
+            return false;
+        }
+        match frac_part {
+            None => (),
+            Some(x) if self.is_float() && !x.is_empty() && x.bytes().all(|b| b.is_ascii_digit()) => (),
+            Some(_) => return false,
+        }
+
+        match *self {
+            ParamType::I8 => s.parse::<i8>().is_ok(),
+            ParamType::I16 => s.parse::<i16>().is_ok(),
+            ParamType::I32 => s.parse::<i32>().is_ok(),
+            ParamType::I64 => s.parse::<i64>().is_ok(),
+            ParamType::U8 => s.parse::<u8>().is_ok(),
+            ParamType::U16 => s.parse::<u16>().is_ok(),
+            ParamType::U32 => s.parse::<u32>().is_ok(),
+            ParamType::U64 => s.parse::<u64>().is_ok(),
+            ParamType::F32 => s.parse::<f32>().is_ok(),
+            ParamType::F64 => s.parse::<f64>().is_ok(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Kind {
+    Type(ParamType),
+    Regex(Regex),
+}
+
+/// Constraint limits what a route param matches.
+///
+/// It is a type name (`int`, `uint`, `float`, `i8` to `i64`, `u8` to `u64`, `f32` or `f64`),
+/// or a regex matching the whole param, like `[a-z-]+`. A constraint cannot contain `/`.
+#[derive(Debug)]
+pub struct Constraint {
+    source: String,
+    kind: Kind,
+}
+
+impl Constraint {
+    /// Panics if `source` is not a type name and not a valid regex.
+    pub fn new(source: &str) -> Constraint {
+        let kind = match ParamType::from_name(source) {
+            Some(t) => Kind::Type(t),
+            None => match Regex::new(&format!("^(?:{})$", source)) {
+                Ok(x) => Kind::Regex(x),
+                Err(err) => panic!("invalid param constraint <{}>: {}", source, err),
+            },
+        };
+
+        Constraint {
+            source: source.to_string(),
+            kind: kind,
+        }
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn matches(&self, s: &str) -> bool {
+        match self.kind {
+            Kind::Type(t) => t.matches(s),
+            Kind::Regex(ref regex) => regex.is_match(s),
+        }
+    }
+
+    /// Returns a regex for this constraint, without anchors.
+    pub fn to_regex(&self) -> String {
+        match self.kind {
+            Kind::Type(t) => t.regex().to_string(),
+            Kind::Regex(_) => format!("(?:{})", self.source),
+        }
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == ',' || c == '_' || c == '-'
+}
+
+/// Parses a param placeholder at the beginning of `s`: `:name`, `:name<constraint>`,
+/// `{name}` or `{name:constraint}`. Returns the name, the constraint and the length of the placeholder.
+pub fn parse_param(s: &str) -> Option<(&str, Option<&str>, usize)> {
+    if s.starts_with(':') {
+        let name_len = s[1..].find(|c: char| !is_name_char(c)).unwrap_or(s.len() - 1);
+        let name = &s[1..(1 + name_len)];
+        let rest = &s[(1 + name_len)..];
+        if rest.starts_with('<') {
+            let end = rest.find('>')?;
+            return Some((name, Some(&rest[1..end]), 1 + name_len + end + 1));
+        }
+        return Some((name, None, 1 + name_len));
+    }
+
+    if s.starts_with('{') {
+        let end = closing_brace(s)?;
+        let inner = &s[1..end];
+        return match inner.find(':') {
+            Some(pos) => Some((&inner[..pos], Some(&inner[(pos + 1)..]), end + 1)),
+            None => Some((inner, None, end + 1)),
+        };
+    }
+
+    None
+}
+
+/// Returns the position of the `}` closing the `{` at the beginning of `s`.
+/// Braces in a constraint like `{id:[0-9]{4}}` are nested, and escaped ones like `\}` are skipped.
+fn closing_brace(s: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            },
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Panics if a constraint in `pattern` contains `/`.
+/// Patterns are split at `/` before placeholders are parsed, so such a constraint could never match.
+pub fn check_constraints(pattern: &str) {
+    let mut pos = 0;
+    while pos < pattern.len() {
+        let rest = &pattern[pos..];
+        match parse_param(rest) {
+            Some((_, constraint, len)) => {
+                if let Some(c) = constraint {
+                    assert!(!c.contains('/'), "param constraint cannot contain /: {}", pattern);
+                }
+                pos += len;
+            },
+            None => pos += rest.chars().next().map_or(1, |c| c.len_utf8()),
+        }
+    }
+}
+
+/// Parses a named tail placeholder at the beginning of `s`: `*name` or `**:name`.
+/// It matches the rest of the path, including `/`. Returns the name and the length of the placeholder.
+pub fn parse_tail(s: &str) -> Option<(&str, usize)> {
+    let start = if s.starts_with("**:") {
+        3
+    } else if s.starts_with('*') && s[1..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+        1
+    } else {
+        return None;
+    };
+
+    let name_len = s[start..].find(|c: char| !is_name_char(c)).unwrap_or(s.len() - start);
+    if name_len == 0 {
+        return None;
+    }
+    Some((&s[start..(start + name_len)], start + name_len))
+}
+
+/// Returns true if `s` has `**` or a named tail, which can match `/`.
+pub fn has_tail(s: &str) -> bool {
+    s.contains("**") || s.match_indices('*').any(|(i, _)| parse_tail(&s[i..]).is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn type_constraints() {
+        let c = Constraint::new("u64");
+        assert!(c.matches("0"));
+        assert!(c.matches("18446744073709551615"));
+        assert!(!c.matches("18446744073709551616"));
+        assert!(!c.matches("-1"));
+        assert!(!c.matches("+1"));
+        assert!(!c.matches(""));
+        assert!(!c.matches("abc"));
+
+        let c = Constraint::new("int");
+        assert!(c.matches("-3"));
+        assert!(!c.matches("-"));
+        assert!(!c.matches("1.5"));
+
+        let c = Constraint::new("u8");
+        assert!(c.matches("255"));
+        assert!(!c.matches("256"));
+
+        let c = Constraint::new("float");
+        assert!(c.matches("1.5"));
+        assert!(c.matches("-2"));
+        assert!(!c.matches("1."));
+        assert!(!c.matches("NaN"));
+    }
+
+    #[test]
+    fn regex_constraints() {
+        let c = Constraint::new("[a-z-]+");
+        assert!(c.matches("hello-world"));
+        assert!(!c.matches("Hello"));
+        assert!(!c.matches("hello world"));
+        assert!(!c.matches(""));
+
+        let c = Constraint::new("a|b");
+        assert!(c.matches("a"));
+        assert!(!c.matches("ab"));
+    }
+
+    #[test]
+    fn parse_placeholder() {
+        assert_eq!(parse_param(":id"), Some(("id", None, 3)));
+        assert_eq!(parse_param(":id.json"), Some(("id", None, 3)));
+        assert_eq!(parse_param(":id<u64>"), Some(("id", Some("u64"), 8)));
+        assert_eq!(parse_param(":slug<[a-z-]+>.html"), Some(("slug", Some("[a-z-]+"), 14)));
+        assert_eq!(parse_param("{id}"), Some(("id", None, 4)));
+        assert_eq!(parse_param("{id:int}"), Some(("id", Some("int"), 8)));
+        assert_eq!(parse_param("{id:[0-9]{4}}"), Some(("id", Some("[0-9]{4}"), 13)));
+        assert_eq!(parse_param("{id:[0-9]{2,4}}.json"), Some(("id", Some("[0-9]{2,4}"), 15)));
+        assert_eq!(parse_param(r"{id:\}+}"), Some(("id", Some(r"\}+"), 8)));
+        assert_eq!(parse_param("{id:[0-9]{4}"), None);
+        assert_eq!(parse_param(":id<u64"), None);
+        assert_eq!(parse_param("id"), None);
+    }
+
+    #[test]
+    fn check_constraints_in_pattern() {
+        check_constraints("/users/:id<u64>/posts/{slug:[a-z]{2,}}");
+        check_constraints("/files/*rest");
+    }
+
+    #[test]
+    #[should_panic(expected = "param constraint cannot contain /")]
+    fn slash_in_constraint() {
+        check_constraints("/files/{path:[a-z/]+}");
+    }
+
+    #[test]
+    fn parse_tail_placeholder() {
+        assert_eq!(parse_tail("*rest"), Some(("rest", 5)));
+        assert_eq!(parse_tail("**:rest"), Some(("rest", 7)));
+        assert_eq!(parse_tail("*"), None);
+        assert_eq!(parse_tail("**"), None);
+        assert_eq!(parse_tail("**:"), None);
+        assert_eq!(parse_tail("*.css"), None);
+
+        assert!(has_tail("/files/*rest"));
+        assert!(has_tail("/files/**"));
+        assert!(!has_tail("/files/*"));
+        assert!(!has_tail("/files/*.css"));
+    }
+}
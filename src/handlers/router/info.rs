@@ -0,0 +1,142 @@
+use std::fmt;
+
+use hyper::Method;
+
+use super::matcher;
+
+/// RouteInfo describes a route added to a Router. See `Router::routes`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RouteInfo {
+    /// The methods of the route. None if it accepts every method.
+    pub methods: Option<Vec<Method>>,
+    /// The pattern as it was added, including the prefixes of mount points.
+    pub pattern: String,
+    pub name: Option<String>,
+    /// The names of the params in `pattern`, including the ones of mount points.
+    pub params: Vec<String>,
+}
+
+impl RouteInfo {
+    pub fn new(methods: Option<Vec<Method>>, pattern: String, name: Option<String>) -> RouteInfo {
+        RouteInfo {
+            methods: methods,
+            params: matcher::param_names(&pattern),
+            pattern: pattern,
+            name: name,
+        }
+    }
+
+    fn accepts(&self, method: &Method) -> bool {
+        match self.methods {
+            Some(ref methods) => methods.contains(method),
+            None => true,
+        }
+    }
+
+    /// Returns the methods both routes accept. None means every method.
+    fn common_methods(&self, other: &RouteInfo) -> Option<Vec<Method>> {
+        match (&self.methods, &other.methods) {
+            (&None, &None) => None,
+            (&Some(ref methods), _) => Some(methods.iter().filter(|m| other.accepts(m)).cloned().collect()),
+            (&None, &Some(ref methods)) => Some(methods.clone()),
+        }
+    }
+
+    fn methods_string(&self) -> String {
+        match self.methods {
+            Some(ref methods) => methods.iter().map(|m| m.to_string()).collect::<Vec<_>>().join(","),
+            None => "*".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for RouteInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:<12} {}", self.methods_string(), self.pattern)?;
+        if let Some(ref name) = self.name {
+            write!(f, " ({})", name)?;
+        }
+        Ok(())
+    }
+}
+
+/// Conflict is a pair of routes matching the same paths with the same methods.
+/// Only one of them can answer such requests; the other one is shadowed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Conflict {
+    /// The route added first. In the same router, this one wins.
+    pub first: RouteInfo,
+    pub second: RouteInfo,
+    /// The methods both routes accept. None means every method.
+    pub methods: Option<Vec<Method>>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} conflicts with {}", self.second, self.first)
+    }
+}
+
+/// Returns the pairs of `routes` having the same shape (see `matcher::shape`) and common methods.
+pub fn find_conflicts(routes: &[RouteInfo]) -> Vec<Conflict> {
+    let shapes: Vec<String> = routes.iter().map(|r| matcher::shape(&r.pattern)).collect();
+    let mut conflicts = Vec::new();
+
+    for (j, second) in routes.iter().enumerate() {
+        for (i, first) in routes[..j].iter().enumerate() {
+            if shapes[i] != shapes[j] {
+                continue;
+            }
+            let methods = first.common_methods(second);
+            if methods.as_ref().map_or(false, |x| x.is_empty()) {
+                continue;
+            }
+            conflicts.push(Conflict {
+                first: first.clone(),
+                second: second.clone(),
+                methods: methods,
+            });
+        }
+    }
+
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn route(methods: Option<&[Method]>, pattern: &str) -> RouteInfo {
+        RouteInfo::new(methods.map(|x| x.to_vec()), pattern.to_string(), None)
+    }
+
+    #[test]
+    fn conflicts() {
+        let routes = vec![
+            route(Some(&[Method::Get]), "/users/:id"),
+            route(Some(&[Method::Post]), "/users/:name"),
+            route(Some(&[Method::Get, Method::Put]), "/users/{user}"),
+            route(Some(&[Method::Get]), "/users/:id<u64>"),
+            route(None, "/files/**"),
+            route(Some(&[Method::Delete]), "/files/*rest"),
+        ];
+
+        let conflicts = find_conflicts(&routes);
+        assert_eq!(conflicts.len(), 2);
+        assert_eq!(conflicts[0].first.pattern, "/users/:id");
+        assert_eq!(conflicts[0].second.pattern, "/users/{user}");
+        assert_eq!(conflicts[0].methods, Some(vec![Method::Get]));
+        assert_eq!(conflicts[1].first.pattern, "/files/**");
+        assert_eq!(conflicts[1].second.pattern, "/files/*rest");
+        assert_eq!(conflicts[1].methods, Some(vec![Method::Delete]));
+    }
+
+    #[test]
+    fn display() {
+        let r = RouteInfo::new(Some(vec![Method::Get, Method::Post]), "/users/:id".to_string(),
+                               Some("user".to_string()));
+        assert_eq!(r.params, vec!["id"]);
+        assert_eq!(r.to_string(), "GET,POST     /users/:id (user)");
+        assert_eq!(route(None, "/").to_string(), "*            /");
+    }
+}
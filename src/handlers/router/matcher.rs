@@ -1,56 +1,313 @@
-use regex::{Captures, Regex};
+use std::ascii::AsciiExt;
 
-static VAR_SEQ: &'static str = "[,.a-zA-Z0-9_-]*";
-static VAR_SEQ_WITH_SLASH: &'static str = "[,./a-zA-Z0-9_-]*";
+use regex::{self, Captures, Regex};
+use url::percent_encoding::percent_decode;
 
+use super::constraint::{self, Constraint};
+
+/// A path segment character of RFC 3986 (pchar), or a non-ASCII character.
+macro_rules! pchar {
+    () => { r"(?:[a-zA-Z0-9._~!$&'()*+,;=:@-]|%[0-9a-fA-F]{2}|[^\x00-\x7F])" }
+}
+
+static VAR_SEQ: &'static str = concat!(pchar!(), "*");
+static VAR_SEQ_WITH_SLASH: &'static str = concat!("(?:", pchar!(), "|/)*");
+/// An unconstrained param matches a non-empty segment.
+static PARAM_SEQ: &'static str = concat!(pchar!(), "+");
+
+fn is_pchar(b: u8) -> bool {
+    b >= 0x80 || b.is_ascii_alphanumeric() || b"-._~!$&'()*+,;=:@".contains(&b)
+}
+
+/// Returns true if `s` is a valid path segment: pchar of RFC 3986 and non-ASCII characters.
+/// `%` must be followed by two hex digits.
+pub fn is_segment(s: &str) -> bool {
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            if i + 2 >= bytes.len() || !bytes[i + 1].is_ascii_hexdigit() || !bytes[i + 2].is_ascii_hexdigit() {
+                return false;
+            }
+            i += 3;
+        } else if is_pchar(bytes[i]) {
+            i += 1;
+        } else {
+            return false;
+        }
+    }
+    true
+}
+
+#[derive(Clone)]
+struct Param {
+    name: String,
+    /// The matched text, with `%xx` escapes.
+    raw: String,
+    /// The percent-decoded text. None if it is not valid UTF-8.
+    decoded: Option<String>,
+}
+
+impl Param {
+    fn new(name: String, raw: String) -> Param {
+        let decoded = percent_decode(raw.as_bytes()).decode_utf8().ok().map(|x| x.into_owned());
+        Param {
+            name: name,
+            raw: raw,
+            decoded: decoded,
+        }
+    }
+}
+
+#[derive(Clone, Default)]
 pub struct RouteResult {
-    params: Vec<(String, String)>,
+    params: Vec<Param>,
 }
 
 impl RouteResult {
-    pub fn param(&self, key: &str) -> Option<&str> {
-        for &(ref k, ref v) in &self.params {
-            if k == key {
-                return Some(&v);
+    /// Makes a result from the matched text of params. They are percent-decoded here.
+    pub fn new(params: Vec<(String, String)>) -> RouteResult {
+        RouteResult {
+            params: params.into_iter().map(|(k, v)| Param::new(k, v)).collect(),
+        }
+    }
+
+    /// Adds the params of `other`. A param already here is overwritten by `other`.
+    pub fn merge(&mut self, other: RouteResult) {
+        for param in other.params {
+            match self.params.iter().position(|p| p.name == param.name) {
+                Some(pos) => self.params[pos] = param,
+                None => self.params.push(param),
             }
         }
+    }
 
-        return None;
+    /// Returns the percent-decoded param.
+    /// None is returned if it doesn't exist, or if it is not valid UTF-8 (see `invalid_param`).
+    pub fn param(&self, key: &str) -> Option<&str> {
+        self.params.iter().find(|p| p.name == key).and_then(|p| p.decoded.as_ref().map(|x| x.as_str()))
+    }
+
+    /// Returns the param as it is in the path, with `%xx` escapes.
+    pub fn raw_param(&self, key: &str) -> Option<&str> {
+        self.params.iter().find(|p| p.name == key).map(|p| p.raw.as_str())
+    }
+
+    /// Returns the name of the first param that is not valid UTF-8 after percent-decoding.
+    pub fn invalid_param(&self) -> Option<&str> {
+        self.params.iter().find(|p| p.decoded.is_none()).map(|p| p.name.as_str())
     }
 }
 
 pub struct Matcher {
     regex: Regex,
+    /// The route pattern this matcher is made from.
+    pattern: String,
+    /// The constraints of the params. The regex doesn't check the range of typed params, so they are
+    /// checked again after the regex matches.
+    constraints: Vec<(String, Constraint)>,
 }
 
 impl Matcher {
     pub fn new(regex: Regex) -> Matcher {
         Matcher {
+            pattern: regex.as_str().to_string(),
             regex: regex,
+            constraints: Vec::new(),
         }
     }
 
+    /// Makes a matcher for a path prefix. `match_prefix` should be used instead of `match_route`.
+    pub fn prefix(s: &str) -> Matcher {
+        let regex = Regex::new(&format!("^{}", to_regex(s))).unwrap();
+        Matcher {
+            regex: regex,
+            pattern: s.to_string(),
+            constraints: constraints(s),
+        }
+    }
+
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Returns true if `other` matches the same paths with the same params.
+    pub fn is_equivalent(&self, other: &Matcher) -> bool {
+        self.regex.as_str() == other.regex.as_str() &&
+            self.constraints.len() == other.constraints.len() &&
+            self.constraints.iter().zip(&other.constraints)
+                .all(|(x, y)| x.0 == y.0 && x.1.source() == y.1.source())
+    }
+
+    /// Returns true if every param satisfies its constraint.
+    fn satisfies(&self, params: &[(&str, &str)]) -> bool {
+        self.constraints.iter().all(|&(ref name, ref constraint)| {
+            params.iter().filter(|p| p.0 == name.as_str()).all(|p| constraint.matches(p.1))
+        })
+    }
+
+    fn to_result(&self, captures: &Captures) -> Option<RouteResult> {
+        let params = self.named(captures);
+        if !self.satisfies(&params) {
+            return None;
+        }
+        Some(RouteResult::new(params.into_iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()))
+    }
+
+    fn named<'t, 'p>(&'t self, captures: &Captures<'p>) -> Vec<(&'t str, &'p str)> {
+        let mut params = Vec::new();
+        for (opt_name, opt_value) in self.regex.capture_names().zip(captures.iter()) {
+            if let (Some(name), Some(value)) = (opt_name, opt_value) {
+                params.push((name, value.as_str()));
+            }
+        }
+        params
+    }
+
     pub fn match_route(&self, path: &str) -> Option<RouteResult> {
-        if !self.regex.is_match(path) {
+        self.regex.captures(path).and_then(|captures| self.to_result(&captures))
+    }
+
+    /// Same as `match_route`, but params are borrowed from `path`.
+    pub fn captures<'t, 'p>(&'t self, path: &'p str) -> Option<Vec<(&'t str, &'p str)>> {
+        let captures = match self.regex.captures(path) {
+            Some(x) => x,
+            None => return None,
+        };
+        let params = self.named(&captures);
+        if self.satisfies(&params) { Some(params) } else { None }
+    }
+
+    /// Matches the beginning of `path` at segment boundary, and returns the rest of `path`.
+    pub fn match_prefix<'a>(&self, path: &'a str) -> Option<(RouteResult, &'a str)> {
+        let captures = match self.regex.captures(path) {
+            Some(x) => x,
+            None => return None,
+        };
+
+        let end = captures.get(0).unwrap().end();
+        let rest = &path[end..];
+        if !rest.is_empty() && !rest.starts_with('/') {
             return None;
         }
 
-        if let Some(captures) = self.regex.captures(path) {
-            let mut params = Vec::new();
+        self.to_result(&captures).map(|result| (result, rest))
+    }
+}
+
 
-            for (opt_name, opt_value) in self.regex.capture_names().zip(captures.iter()) {
-                if let (Some(name), Some(value)) = (opt_name, opt_value) {
-                    params.push((name.to_string(), value.as_str().to_string()));
-                }
+/// Converts a route pattern to a regex without anchors.
+pub fn to_regex(s: &str) -> String {
+    let mut regex = String::new();
+    let mut pos = 0;
+
+    while pos < s.len() {
+        let rest = &s[pos..];
+        if let Some((name, len)) = constraint::parse_tail(rest) {
+            regex.push_str(&format!("(?P<{}>{})", name, VAR_SEQ_WITH_SLASH));
+            pos += len;
+        } else if rest.starts_with("**") {
+            regex.push_str(VAR_SEQ_WITH_SLASH);
+            pos += 2;
+        } else if rest.starts_with('*') {
+            regex.push_str(VAR_SEQ);
+            pos += 1;
+        } else if let Some((name, constraint, len)) = constraint::parse_param(rest) {
+            let param_regex = match constraint {
+                Some(c) => Constraint::new(c).to_regex(),
+                None => PARAM_SEQ.to_string(),
+            };
+            regex.push_str(&format!("(?P<{}>{})", name, param_regex));
+            pos += len;
+        } else {
+            let c = rest.chars().next().unwrap();
+            regex.push_str(&regex::escape(&c.to_string()));
+            pos += c.len_utf8();
+        }
+    }
+
+    regex
+}
+
+/// Returns the constraints of the params in a route pattern, in order.
+fn constraints(pattern: &str) -> Vec<(String, Constraint)> {
+    let mut constraints = Vec::new();
+    let mut pos = 0;
+
+    while pos < pattern.len() {
+        let rest = &pattern[pos..];
+        if let Some((_, len)) = constraint::parse_tail(rest) {
+            pos += len;
+        } else if rest.starts_with('*') {
+            pos += 1;
+        } else if let Some((name, constraint, len)) = constraint::parse_param(rest) {
+            if let Some(c) = constraint {
+                constraints.push((name.to_string(), Constraint::new(c)));
             }
+            pos += len;
+        } else {
+            pos += rest.chars().next().unwrap().len_utf8();
+        }
+    }
 
-            return Some(RouteResult {
-                params: params,
-            });
-        };
+    constraints
+}
+
+/// Returns the names of the params in a route pattern, in order.
+pub fn param_names(pattern: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let mut pos = 0;
+
+    while pos < pattern.len() {
+        let rest = &pattern[pos..];
+        if let Some((name, len)) = constraint::parse_tail(rest) {
+            names.push(name.to_string());
+            pos += len;
+        } else if rest.starts_with('*') {
+            pos += 1;
+        } else if let Some((name, _, len)) = constraint::parse_param(rest) {
+            names.push(name.to_string());
+            pos += len;
+        } else {
+            pos += rest.chars().next().unwrap().len_utf8();
+        }
+    }
 
-        return None;
+    names
+}
+
+/// Returns the shape of a route pattern: param names are dropped and the placeholder syntax is unified,
+/// so that patterns matching the same paths have the same shape, e.g. `/users/:<u64>` for
+/// `/users/:id<u64>` and `/users/{user:u64}`.
+pub fn shape(pattern: &str) -> String {
+    let mut shape = String::new();
+    let mut pos = 0;
+
+    while pos < pattern.len() {
+        let rest = &pattern[pos..];
+        if let Some((_, len)) = constraint::parse_tail(rest) {
+            shape.push_str("**");
+            pos += len;
+        } else if rest.starts_with("**") {
+            shape.push_str("**");
+            pos += 2;
+        } else if rest.starts_with('*') {
+            shape.push('*');
+            pos += 1;
+        } else if let Some((_, constraint, len)) = constraint::parse_param(rest) {
+            shape.push(':');
+            if let Some(c) = constraint {
+                shape.push_str(&format!("<{}>", c));
+            }
+            pos += len;
+        } else {
+            let c = rest.chars().next().unwrap();
+            shape.push(c);
+            pos += c.len_utf8();
+        }
     }
+
+    shape
 }
 
 impl<'a> From<&'a str> for Matcher {
@@ -61,23 +318,13 @@ impl<'a> From<&'a str> for Matcher {
 
 impl From<String> for Matcher {
     fn from(s: String) -> Matcher {
-        let regex_var_seq: Regex = Regex::new(r":([,a-zA-Z0-9_-]*)").unwrap();
-
-        let with_placeholder = s.replace("**", "__DOUBLEWILDCARD__");
-        let star_replaced = with_placeholder.replace("*", VAR_SEQ);
-        let wildcarded = star_replaced.replace("__DOUBLEWILDCARD__", VAR_SEQ_WITH_SLASH);
-
-        let named_captures = regex_var_seq.replace_all(&wildcarded, |captures: &Captures| {
-            let c = captures.iter().skip(1).next().unwrap();
-            // println!("c={}", c.unwrap().as_str());
-            format!("(?P<{}>[,a-zA-Z0-9%_-]*)", c.unwrap().as_str())
-        });
-
-        let regex_str = format!("^{}$", named_captures.to_string());
-
-        // println!("regex={}", regex_str);
+        let regex_str = format!("^{}$", to_regex(&s));
         let regex = Regex::new(&regex_str).unwrap();
-        Matcher::new(regex)
+        Matcher {
+            regex: regex,
+            constraints: constraints(&s),
+            pattern: s,
+        }
     }
 }
 
@@ -207,4 +454,106 @@ mod tests {
 
         assert!(matcher.match_route("/public/foo/bar.css").is_some());
     }
+
+    #[test]
+    fn test_prefix_match() {
+        let matcher = Matcher::prefix("/users/:user");
+
+        assert!(matcher.match_prefix("/").is_none());
+        assert!(matcher.match_prefix("/users").is_none());
+
+        {
+            let (rr, rest) = matcher.match_prefix("/users/foo").unwrap();
+            assert_eq!(rr.param("user"), Some("foo"));
+            assert_eq!(rest, "");
+        }
+        {
+            let (rr, rest) = matcher.match_prefix("/users/foo/issues/1").unwrap();
+            assert_eq!(rr.param("user"), Some("foo"));
+            assert_eq!(rest, "/issues/1");
+        }
+
+        let matcher = Matcher::prefix("/api");
+        assert!(matcher.match_prefix("/apis").is_none());
+        assert_eq!(matcher.match_prefix("/api/v1").map(|(_, rest)| rest), Some("/v1"));
+    }
+
+    #[test]
+    fn test_merge_result() {
+        let mut rr = Matcher::from("/:a/:b").match_route("/x/y").unwrap();
+        rr.merge(Matcher::from("/:b/:c").match_route("/z/w").unwrap());
+
+        assert_eq!(rr.param("a"), Some("x"));
+        assert_eq!(rr.param("b"), Some("z"));
+        assert_eq!(rr.param("c"), Some("w"));
+    }
+
+    #[test]
+    fn test_pchar_params() {
+        let matcher: Matcher = "/users/:name".into();
+
+        for name in &["a~b", "a@b", "a:b", "a+b", "a.b", "(a)", "%E6%97%A5", "日本"] {
+            let path = format!("/users/{}", name);
+            assert!(matcher.match_route(&path).is_some(), "{}", path);
+        }
+        assert!(matcher.match_route("/users/a b").is_none());
+        assert!(matcher.match_route("/users/a?b").is_none());
+        assert!(matcher.match_route("/users/a%2").is_none());
+
+        assert!(is_segment("a~b@c:d+e"));
+        assert!(is_segment("%41%e6"));
+        assert!(is_segment(""));
+        assert!(!is_segment("%4"));
+        assert!(!is_segment("%zz"));
+        assert!(!is_segment("a/b"));
+        assert!(!is_segment("a\"b"));
+    }
+
+    #[test]
+    fn test_decoded_params() {
+        let matcher: Matcher = "/users/:name".into();
+
+        let rr = matcher.match_route("/users/%E6%97%A5%E6%9C%AC%20x").unwrap();
+        assert_eq!(rr.param("name"), Some("日本 x"));
+        assert_eq!(rr.raw_param("name"), Some("%E6%97%A5%E6%9C%AC%20x"));
+        assert_eq!(rr.invalid_param(), None);
+
+        let rr = matcher.match_route("/users/a+b%2Fc").unwrap();
+        assert_eq!(rr.param("name"), Some("a+b/c"));
+
+        let rr = matcher.match_route("/users/%FF").unwrap();
+        assert_eq!(rr.param("name"), None);
+        assert_eq!(rr.raw_param("name"), Some("%FF"));
+        assert_eq!(rr.invalid_param(), Some("name"));
+    }
+
+    #[test]
+    fn test_named_tail() {
+        for pattern in &["/files/*rest", "/files/**:rest"] {
+            let matcher: Matcher = (*pattern).into();
+
+            let rr = matcher.match_route("/files/a/b%20c.txt").unwrap();
+            assert_eq!(rr.param("rest"), Some("a/b c.txt"));
+            assert_eq!(rr.raw_param("rest"), Some("a/b%20c.txt"));
+            assert_eq!(matcher.match_route("/files/").unwrap().param("rest"), Some(""));
+            assert!(matcher.match_route("/files").is_none());
+        }
+
+        let matcher: Matcher = "/repos/:repo/*path/raw".into();
+        let rr = matcher.match_route("/repos/foo/src/lib.rs/raw").unwrap();
+        assert_eq!(rr.param("repo"), Some("foo"));
+        assert_eq!(rr.param("path"), Some("src/lib.rs"));
+    }
+
+    #[test]
+    fn test_param_names_and_shape() {
+        assert_eq!(param_names("/:user/issues/{id:u64}/*rest"), vec!["user", "id", "rest"]);
+        assert!(param_names("/public/**").is_empty());
+
+        assert_eq!(shape("/users/:id<u64>"), "/users/:<u64>");
+        assert_eq!(shape("/users/{user:u64}"), "/users/:<u64>");
+        assert_eq!(shape("/users/:name.json"), "/users/:.json");
+        assert_eq!(shape("/files/*rest"), shape("/files/**"));
+        assert!(shape("/files/*") != shape("/files/**"));
+    }
 }
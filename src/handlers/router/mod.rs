@@ -1,72 +1,889 @@
+mod constraint;
+mod info;
 mod matcher;
+mod tree;
+mod url_map;
 
-use hyper::Method;
+use std::collections::HashMap;
+use std::mem;
+
+use futures::future;
+use futures::{Future, Stream};
+use hyper::{Body, Method};
+use hyper::header::{Allow, ContentLength, Headers};
+use HyperResponse;
+use middleware::{Middleware, Next};
 use prelude::*;
 use std::sync::Arc;
 
-use self::matcher::Matcher;
-pub use self::matcher::RouteResult;
+use self::tree::{Captures, Node};
+pub use self::info::{Conflict, RouteInfo};
+pub use self::matcher::{Matcher, RouteResult};
+pub use self::url_map::UrlMap;
+
+/// The methods a route accepts.
+enum Methods {
+    Any,
+    Only(Vec<Method>),
+}
+
+impl Methods {
+    fn contains(&self, method: &Method) -> bool {
+        match *self {
+            Methods::Any => true,
+            Methods::Only(ref methods) => methods.contains(method),
+        }
+    }
+}
 
 pub struct Route<A: ZirconApp> {
-    method: Method,
+    methods: Methods,
     matcher: Matcher,
-    handler: Box<Handler<A>>,
+    handler: Arc<Handler<A>>,
+    name: Option<String>,
+}
+
+impl<A: ZirconApp> Route<A> {
+    /// Names this route for `UrlMap::url_for`.
+    ///
+    /// ```ignore
+    /// router.get("/issues/:id", show_issue).name("issue");
+    /// ```
+    pub fn name(&mut self, name: &str) -> &mut Route<A> {
+        self.name = Some(name.to_string());
+        self
+    }
+}
+
+/// Entry is a route, or a router mounted at a prefix.
+enum Entry<A: ZirconApp> {
+    Route(Route<A>),
+    Mount(Matcher, Router<A>),
+}
+
+/// Found is a route chosen for a request.
+struct Found<'a, A: ZirconApp + 'a> {
+    route: &'a Route<A>,
+    params: RouteResult,
+    /// The path under the innermost mount point. None if not mounted.
+    path: Option<String>,
+    /// Middlewares of the routers on the way to the route, outermost first.
+    middlewares: Vec<Arc<Middleware<A>>>,
+}
+
+/// Scope is what a router on the way to a route gives to it: the params captured in the router,
+/// and its middlewares. It is borrowed during the walk, and copied out only for a route taken.
+struct Scope<'s, 'a: 's, 'p: 's, A: ZirconApp + 'a> {
+    outer: Option<&'s Scope<'s, 'a, 'p, A>>,
+    captures: &'s Captures<'a, 'p>,
+    middlewares: &'a [Arc<Middleware<A>>],
+}
+
+impl<'s, 'a, 'p, A: ZirconApp> Scope<'s, 'a, 'p, A> {
+    /// Makes the owned Found for `route`. `path` is the path under the innermost mount point.
+    fn found(&self, route: &'a Route<A>, path: Option<&str>) -> Found<'a, A> {
+        let mut found = Found {
+            route: route,
+            params: RouteResult::default(),
+            path: path.map(|x| x.to_string()),
+            middlewares: Vec::new(),
+        };
+        self.collect(&mut found);
+        found
+    }
+
+    /// Adds the params and the middlewares of this scope to `found`, after the outer ones.
+    fn collect(&self, found: &mut Found<'a, A>) {
+        if let Some(outer) = self.outer {
+            outer.collect(found);
+        }
+        let captures = self.captures.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect();
+        found.params.merge(RouteResult::new(captures));
+        found.middlewares.extend(self.middlewares.iter().cloned());
+    }
+}
+
+/// Lookup collects what the routes matching the path (but not the method) say,
+/// across mounted routers.
+struct Lookup<'a, A: ZirconApp + 'a> {
+    /// Methods of the routes matching the path, for 405 and OPTIONS.
+    allowed: Vec<Method>,
+    /// A GET route to answer HEAD with, unless a HEAD route exists.
+    get_route: Option<Found<'a, A>>,
+}
+
+impl<'a, A: ZirconApp> Lookup<'a, A> {
+    fn new() -> Lookup<'a, A> {
+        Lookup {
+            allowed: Vec::new(),
+            get_route: None,
+        }
+    }
 }
 
+/// What Router does when no route matches a path, but the path with or without
+/// the trailing slash matches one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TrailingSlash {
+    /// `/foo` and `/foo/` are different paths. Not found is returned. This is the default.
+    Strict,
+    /// Redirects to the other form with 301 Moved Permanently.
+    MovedPermanently,
+    /// Redirects to the other form with 308 Permanent Redirect, which keeps the method and the body.
+    PermanentRedirect,
+}
+
+/// Router dispatches requests by method and path.
+///
+/// When several routes match a path, static segments win over `:name`, which wins over `*`,
+/// which wins over `**`. Routes of the same precedence are tried in the order they are added.
+///
+/// `*name` or `**:name` matches the rest of the path like `**`, and it is available as a param,
+/// e.g. `req.param("rest")` is `a/b.txt` for `/files/a/b.txt` and the route `/files/*rest`.
 pub struct Router<A: ZirconApp> {
-    routes: Vec<Route<A>>,
+    entries: Vec<Entry<A>>,
+    /// Indexes of `entries` by path.
+    tree: Node<usize>,
+    /// Handles requests no route matches.
+    fallback: Option<Box<Handler<A>>>,
+    trailing_slash: TrailingSlash,
+    middlewares: Vec<Arc<Middleware<A>>>,
 }
 
 impl<A: ZirconApp> Router<A> {
     pub fn new() -> Router<A> {
         Router {
-            routes: Vec::new(),
+            entries: Vec::new(),
+            tree: Node::new(),
+            fallback: None,
+            trailing_slash: TrailingSlash::Strict,
+            middlewares: Vec::new(),
         }
     }
 
-    pub fn add_route<H: Handler<A>>(&mut self, method: Method, path: &str, handler: H) {
-        self.routes.push(Route {
-            method: method,
+    fn push<H: Handler<A>>(&mut self, methods: Methods, path: &str, handler: H) -> &mut Route<A> {
+        self.tree.insert(path, self.entries.len());
+        self.entries.push(Entry::Route(Route {
+            methods: methods,
             matcher: path.into(),
-            handler: Box::new(handler),
+            handler: Arc::new(handler),
+            name: None,
+        }));
+
+        match self.entries.last_mut() {
+            Some(&mut Entry::Route(ref mut route)) => route,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn add_route<H: Handler<A>>(&mut self, method: Method, path: &str, handler: H) -> &mut Route<A> {
+        self.push(Methods::Only(vec![method]), path, handler)
+    }
+
+    /// Adds a route shared by several methods. The handler can tell them by `req.method()`.
+    pub fn add_route_with_methods<H: Handler<A>>(&mut self, methods: &[Method], path: &str, handler: H)
+                                                 -> &mut Route<A> {
+        self.push(Methods::Only(methods.to_vec()), path, handler)
+    }
+
+    pub fn get<H: Handler<A>>(&mut self, path: &str, handler: H) -> &mut Route<A> {
+        self.add_route(Method::Get, path, handler)
+    }
+
+    pub fn post<H: Handler<A>>(&mut self, path: &str, handler: H) -> &mut Route<A> {
+        self.add_route(Method::Post, path, handler)
+    }
+
+    pub fn put<H: Handler<A>>(&mut self, path: &str, handler: H) -> &mut Route<A> {
+        self.add_route(Method::Put, path, handler)
+    }
+
+    pub fn delete<H: Handler<A>>(&mut self, path: &str, handler: H) -> &mut Route<A> {
+        self.add_route(Method::Delete, path, handler)
+    }
+
+    pub fn patch<H: Handler<A>>(&mut self, path: &str, handler: H) -> &mut Route<A> {
+        self.add_route(Method::Patch, path, handler)
+    }
+
+    /// Adds an OPTIONS route. Without one, OPTIONS is answered from the route table.
+    pub fn options<H: Handler<A>>(&mut self, path: &str, handler: H) -> &mut Route<A> {
+        self.add_route(Method::Options, path, handler)
+    }
+
+    /// Adds a route for every method.
+    pub fn any<H: Handler<A>>(&mut self, path: &str, handler: H) -> &mut Route<A> {
+        self.push(Methods::Any, path, handler)
+    }
+
+    /// Mounts `router` at `prefix`. The prefix can have params like `/users/:user`,
+    /// which are merged with the params of the child routes.
+    ///
+    /// Child routes see the path under the prefix, e.g. `/api/users` is `/users` in a router
+    /// mounted at `/api`. The prefix itself is `/`. If no child route matches, routes added
+    /// after the mount are tried.
+    pub fn mount(&mut self, prefix: &str, router: Router<A>) {
+        let prefix = prefix.trim_right_matches('/');
+        self.tree.insert_mount(prefix, self.entries.len());
+        self.entries.push(Entry::Mount(Matcher::prefix(prefix), router));
+    }
+
+    /// Adds the routes built by `f` under `prefix`.
+    ///
+    /// ```ignore
+    /// router.scope("/api/v1", |r| {
+    ///     r.get("/users/:id", show_user);
+    /// });
+    /// ```
+    pub fn scope<F: FnOnce(&mut Router<A>)>(&mut self, prefix: &str, f: F) {
+        let mut router = Router::new();
+        f(&mut router);
+        self.mount(prefix, router);
+    }
+
+    /// Sets the handler for requests no route matches, e.g. to serve `index.html` of a single page app.
+    /// Without it, 404 Not Found is returned. If a route matches the path but not the method,
+    /// 405 Method Not Allowed is returned as before. Fallbacks of mounted routers are not used.
+    pub fn fallback<H: Handler<A>>(&mut self, handler: H) {
+        self.fallback = Some(Box::new(handler));
+    }
+
+    /// Sets the policy for a path that matches only with or without the trailing slash.
+    /// The redirect is tried before the fallback handler.
+    pub fn trailing_slash(&mut self, policy: TrailingSlash) {
+        self.trailing_slash = policy;
+    }
+
+    /// Adds a middleware to the routes of this router, including the ones of mounted routers.
+    /// Middlewares of outer routers run first. It is not used when no route matches,
+    /// e.g. for 404, 405, OPTIONS answered from the route table, or the fallback handler.
+    ///
+    /// ```ignore
+    /// router.scope("/admin", |r| {
+    ///     r.middleware(RequireLogin);
+    ///     r.get("/users", list_users);
+    /// });
+    /// ```
+    pub fn middleware<M: Middleware<A>>(&mut self, middleware: M) {
+        self.middlewares.push(Arc::new(middleware));
+    }
+
+    /// Collects the named routes. Routes added after this are not included.
+    pub fn url_map(&self) -> UrlMap {
+        let mut patterns = HashMap::new();
+        for route in self.routes() {
+            let name = match route.name {
+                Some(x) => x,
+                None => continue,
+            };
+            if patterns.contains_key(&name) {
+                warn!("route name {} is used more than once; the first one is used", name);
+                continue;
+            }
+            patterns.insert(name, route.pattern);
+        }
+        UrlMap::new(patterns)
+    }
+
+    /// Returns the path of the route named `name`. See `UrlMap::url_for`.
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Result<String, String> {
+        self.url_map().url_for(name, params)
+    }
+
+    /// Returns the routes in the order they are added, including the ones of mounted routers.
+    pub fn routes(&self) -> Vec<RouteInfo> {
+        let mut routes = Vec::new();
+        self.collect_routes("", &mut routes);
+        routes
+    }
+
+    fn collect_routes(&self, prefix: &str, routes: &mut Vec<RouteInfo>) {
+        for entry in &self.entries {
+            match *entry {
+                Entry::Route(ref route) => {
+                    let methods = match route.methods {
+                        Methods::Any => None,
+                        Methods::Only(ref methods) => Some(methods.clone()),
+                    };
+                    // The root of a mounted router is the mount point itself.
+                    let pattern = match route.matcher.pattern() {
+                        "/" if !prefix.is_empty() => prefix.to_string(),
+                        x => format!("{}{}", prefix, x),
+                    };
+                    routes.push(RouteInfo::new(methods, pattern, route.name.clone()));
+                },
+                Entry::Mount(ref matcher, ref router) => {
+                    router.collect_routes(&format!("{}{}", prefix, matcher.pattern()), routes);
+                },
+            }
+        }
+    }
+
+    /// Returns the pairs of routes matching the same paths with common methods,
+    /// e.g. `GET /users/:id` and `GET /users/:name`. The second one of a pair is never reached
+    /// if both are in the same router.
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        info::find_conflicts(&self.routes())
+    }
+
+    /// Returns the routes as a table, one route per line, to print at startup.
+    pub fn route_table(&self) -> String {
+        let mut table = String::new();
+        for route in self.routes() {
+            table.push_str(&route.to_string());
+            table.push('\n');
+        }
+        table
+    }
+
+    /// Returns the params of the route for `method` and `path`, or None if no route matches.
+    /// HEAD and OPTIONS are not answered implicitly here.
+    pub fn recognize(&self, method: &Method, path: &str) -> Option<RouteResult> {
+        let mut state = Lookup::new();
+        self.lookup(path, false, method, None, &mut state).map(|found| found.params)
+    }
+
+    /// Returns the route for `method` and `path`. Routes matching only the path are recorded in `state`.
+    /// Nothing is copied while walking the tree; params and middlewares are collected only for a route taken.
+    fn lookup<'s, 'a, 'p>(&'a self, path: &'p str, mounted: bool, method: &Method,
+                          outer: Option<&'s Scope<'s, 'a, 'p, A>>, state: &mut Lookup<'a, A>)
+                          -> Option<Found<'a, A>> {
+        let mut result = None;
+
+        self.tree.walk(path, &mut |&index, captures, rest| {
+            let scope = Scope {
+                outer: outer,
+                captures: captures,
+                middlewares: &self.middlewares,
+            };
+
+            match self.entries[index] {
+                Entry::Route(ref route) => {
+                    let path = if mounted { Some(path) } else { None };
+                    let methods = match route.methods {
+                        Methods::Only(ref methods) if !methods.contains(method) => methods,
+                        _ => {
+                            result = Some(scope.found(route, path));
+                            return true;
+                        },
+                    };
+                    for m in methods {
+                        if !state.allowed.contains(m) {
+                            state.allowed.push(m.clone());
+                        }
+                    }
+                    if methods.contains(&Method::Get) && state.get_route.is_none() {
+                        state.get_route = Some(scope.found(route, path));
+                    }
+                    false
+                },
+                Entry::Mount(_, ref router) => {
+                    let rest = match rest {
+                        Some("") | None => "/",
+                        Some(x) => x,
+                    };
+                    result = router.lookup(rest, true, method, Some(&scope), state);
+                    result.is_some()
+                },
+            }
         });
+
+        result
     }
+}
 
-    pub fn get<H: Handler<A>>(&mut self, path: &str, handler: H) {
-        let route = Route {
-            method: Method::Get,
-            matcher: path.into(),
-            handler: Box::new(handler),
+impl<A: ZirconApp> Router<A> {
+    /// Redirects to the path with or without the trailing slash if a route matches it.
+    fn redirect_trailing_slash(&self, req: &Request) -> Option<HandlerResult> {
+        let status = match self.trailing_slash {
+            TrailingSlash::Strict => return None,
+            TrailingSlash::MovedPermanently => StatusCode::MovedPermanently,
+            TrailingSlash::PermanentRedirect => StatusCode::PermanentRedirect,
         };
 
-        self.routes.push(route);
+        let path = req.path();
+        let full_path = req.uri().path();
+        if path == "/" || full_path == "/" {
+            return None;
+        }
+
+        let mut state = Lookup::new();
+        let other = toggle_trailing_slash(path);
+        let found = self.lookup(&other, false, req.method(), None, &mut state);
+        if found.is_none() && state.allowed.is_empty() {
+            return None;
+        }
+
+        // The location is made from the requested URI, since `path` can be under a mount point.
+        let mut location = toggle_trailing_slash(full_path);
+        if let Some(query) = req.uri().query() {
+            location.push('?');
+            location.push_str(query);
+        }
+        Some(Response::redirect(&location).with_status(status).render())
     }
+}
 
-    pub fn post<H: Handler<A>>(&mut self, path: &str, handler: H) {
-        let route = Route {
-            method: Method::Post,
-            matcher: path.into(),
-            handler: Box::new(handler),
-        };
+fn toggle_trailing_slash(path: &str) -> String {
+    if path.ends_with('/') {
+        path[..(path.len() - 1)].to_string()
+    } else {
+        format!("{}/", path)
+    }
+}
+
+impl<'a, A: ZirconApp> Found<'a, A> {
+    fn handle(self, app: Arc<A>, mut req: Request) -> HandlerResult {
+        if let Some(name) = self.params.invalid_param() {
+            return ZirconError::render_error_message(StatusCode::BadRequest,
+                                                     format!("param {} is not valid UTF-8", name));
+        }
+        if self.path.is_some() {
+            req.set_modified_path(self.path);
+        }
+        // Params of outer routers (e.g. a Router behind a MountHandler) are kept.
+        let mut params = req.header.params.take().unwrap_or_default();
+        params.merge(self.params);
+        req.set_params(Some(params));
 
-        self.routes.push(route);
+        if self.middlewares.is_empty() {
+            return self.route.handler.handle(app, req);
+        }
+        Next::new(Arc::new(self.middlewares), self.route.handler.clone()).run(app, req)
     }
 }
 
 impl<A: ZirconApp> Handler<A> for Router<A> {
-    fn handle(&self, app: Arc<A>, mut req: Request) -> HandlerResult {
-        for route in &self.routes {
-            if route.method != *req.method() {
-                continue;
+    fn handle(&self, app: Arc<A>, req: Request) -> HandlerResult {
+        let mut state = Lookup::new();
+
+        let found = self.lookup(req.path(), false, req.method(), None, &mut state);
+        if let Some(found) = found {
+            return found.handle(app, req);
+        }
+
+        let mut allowed = state.allowed;
+
+        // Nothing matched.
+        if allowed.is_empty() {
+            if let Some(result) = self.redirect_trailing_slash(&req) {
+                return result;
             }
+            if let Some(ref fallback) = self.fallback {
+                return fallback.handle(app, req);
+            }
+            return ZirconError::render_error_status(StatusCode::NotFound);
+        }
 
-            if let Some(result) = route.matcher.match_route(req.path()) {
-                req.set_params(Some(result));
-                return route.handler.handle(app, req);
+        // HEAD is the GET without body.
+        if let Some(found) = state.get_route {
+            if *req.method() == Method::Head {
+                return Box::new(found.handle(app, req).and_then(head_response));
+            }
+            if !allowed.contains(&Method::Head) {
+                allowed.push(Method::Head);
             }
         }
 
-        // Nothing matched.
-        return ZirconError::render_error_status(StatusCode::NotFound);
+        // OPTIONS is answered from the route table unless a route handles it.
+        if !allowed.contains(&Method::Options) {
+            allowed.push(Method::Options);
+        }
+        if *req.method() == Method::Options {
+            return Response::new()
+                .with_header(Allow(allowed))
+                .with_header(ContentLength(0))
+                .render();
+        }
+
+        ZirconError::MethodNotAllowed(allowed).render()
+    }
+}
+
+/// Makes the response to HEAD from the one to GET. The headers are kept. When the GET handler
+/// didn't set Content-Length, the body is read to count it, so a route streaming an endless body
+/// should handle HEAD by itself.
+fn head_response(mut resp: Response) -> HandlerResult {
+    if resp.origin.headers().has::<ContentLength>() || resp.origin.body_ref().is_none() {
+        resp.origin.set_body(Body::empty());
+        return Box::new(future::ok(resp));
+    }
+
+    let status = resp.origin.status();
+    let headers = mem::replace(resp.origin.headers_mut(), Headers::new());
+    let origin = HyperResponse::new().with_status(status).with_headers(headers);
+    let body = mem::replace(&mut resp.origin, origin).body();
+    Box::new(body.concat2().map_err(ZirconError::HyperError).map(move |body| {
+        resp.origin.headers_mut().set(ContentLength(body.len() as u64));
+        resp
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Stream;
+    use hyper::server::Request as HyperRequest;
+
+    type App = ZirconDefaultApp<()>;
+
+    struct Text(&'static str);
+
+    impl Handler<App> for Text {
+        fn handle(&self, _app: Arc<App>, _req: Request) -> HandlerResult {
+            Response::text(self.0).render()
+        }
+    }
+
+    fn text(s: &'static str) -> Text {
+        Text(s)
+    }
+
+    fn call(router: &Router<App>, method: Method, path: &str) -> Result<Response, ZirconError> {
+        let app = Arc::new(ZirconDefaultApp::<()>::from_config(ZirconConfig::dev()));
+        let req = Request::from_internal(HyperRequest::new(method, path.parse().unwrap()));
+        router.handle(app, req).wait()
+    }
+
+    fn allow(methods: &[Method]) -> Allow {
+        Allow(methods.to_vec())
+    }
+
+    #[test]
+    fn method_not_allowed() {
+        let mut router = Router::new();
+        router.get("/foo", text("get"));
+        router.post("/foo", text("post"));
+        router.get("/bar", text("bar"));
+
+        assert!(call(&router, Method::Get, "/foo").is_ok());
+        match call(&router, Method::Delete, "/foo") {
+            Err(ZirconError::MethodNotAllowed(methods)) => {
+                assert_eq!(methods, vec![Method::Get, Method::Post, Method::Head, Method::Options]);
+            },
+            _ => panic!("405 is expected"),
+        }
+        match call(&router, Method::Delete, "/baz") {
+            Err(ZirconError::Status(StatusCode::NotFound)) => (),
+            _ => panic!("404 is expected"),
+        }
+    }
+
+    #[test]
+    fn automatic_options() {
+        let mut router = Router::new();
+        router.get("/foo", text("get"));
+        router.post("/foo", text("post"));
+
+        let resp = call(&router, Method::Options, "/foo").ok().unwrap();
+        assert_eq!(resp.origin.status(), StatusCode::Ok);
+        assert_eq!(resp.origin.headers().get::<Allow>(),
+                   Some(&allow(&[Method::Get, Method::Post, Method::Head, Method::Options])));
+
+        router.add_route(Method::Options, "/foo", text("options"));
+        let resp = call(&router, Method::Options, "/foo").ok().unwrap();
+        assert_eq!(resp.origin.headers().get::<Allow>(), None);
+    }
+
+    #[test]
+    fn implicit_head() {
+        let mut router = Router::new();
+        router.get("/foo", text("get"));
+
+        let resp = call(&router, Method::Head, "/foo").ok().unwrap();
+        assert_eq!(resp.origin.status(), StatusCode::Ok);
+        assert_eq!(resp.origin.headers().get::<ContentLength>(), Some(&ContentLength(3)));
+        let body = resp.origin.body().concat2().wait().ok().unwrap();
+        assert!(body.is_empty());
+
+        router.add_route(Method::Head, "/foo", text("head"));
+        let resp = call(&router, Method::Head, "/foo").ok().unwrap();
+        assert_eq!(resp.origin.headers().get::<ContentLength>(), Some(&ContentLength(4)));
+
+        // Content-Length is counted when the GET handler doesn't set it.
+        router.get("/bar", |_app: Arc<App>, _req: Request| -> HandlerResult {
+            let mut resp = Response::new().with_status(StatusCode::Created);
+            resp.origin.set_body("streamed");
+            resp.render()
+        });
+        let resp = call(&router, Method::Head, "/bar").ok().unwrap();
+        assert_eq!(resp.origin.status(), StatusCode::Created);
+        assert_eq!(resp.origin.headers().get::<ContentLength>(), Some(&ContentLength(8)));
+        let body = resp.origin.body().concat2().wait().ok().unwrap();
+        assert!(body.is_empty());
+    }
+
+    #[test]
+    fn multi_method_routes() {
+        let mut router = Router::new();
+        router.put("/foo", text("put"));
+        router.add_route_with_methods(&[Method::Get, Method::Patch], "/foo", text("get or patch"));
+        router.any("/bar", text("any"));
+
+        assert!(call(&router, Method::Put, "/foo").is_ok());
+        assert!(call(&router, Method::Patch, "/foo").is_ok());
+        assert!(call(&router, Method::Head, "/foo").is_ok());
+        match call(&router, Method::Delete, "/foo") {
+            Err(ZirconError::MethodNotAllowed(methods)) => {
+                assert_eq!(methods, vec![Method::Put, Method::Get, Method::Patch, Method::Head, Method::Options]);
+            },
+            _ => panic!("405 is expected"),
+        }
+
+        assert!(call(&router, Method::Delete, "/bar").is_ok());
+        assert!(call(&router, Method::Extension("PURGE".to_string()), "/bar").is_ok());
+    }
+
+    struct Echo;
+
+    impl Handler<App> for Echo {
+        fn handle(&self, _app: Arc<App>, req: Request) -> HandlerResult {
+            let s = format!("{} user={:?} id={:?}", req.path(), req.param("user"), req.param("id"));
+            Response::text(s).render()
+        }
+    }
+
+    fn body(resp: Response) -> String {
+        let body = resp.origin.body().concat2().wait().ok().unwrap();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn nested_routers() {
+        let mut issues = Router::new();
+        issues.get("/issues/:id", Echo);
+
+        let mut router = Router::new();
+        router.scope("/api/v1", |r| {
+            r.mount("/users/:user", issues);
+            r.get("/", Echo);
+        });
+        router.get("/api/v1/users/:user/other", Echo);
+
+        let resp = call(&router, Method::Get, "/api/v1/users/foo/issues/3").ok().unwrap();
+        assert_eq!(body(resp), "/issues/3 user=Some(\"foo\") id=Some(\"3\")");
+        let resp = call(&router, Method::Get, "/api/v1").ok().unwrap();
+        assert_eq!(body(resp), "/ user=None id=None");
+
+        // Routes after the mount are tried when the mounted router doesn't match.
+        let resp = call(&router, Method::Get, "/api/v1/users/foo/other").ok().unwrap();
+        assert_eq!(body(resp), "/api/v1/users/foo/other user=Some(\"foo\") id=None");
+
+        match call(&router, Method::Post, "/api/v1/users/foo/issues/3") {
+            Err(ZirconError::MethodNotAllowed(methods)) => {
+                assert_eq!(methods, vec![Method::Get, Method::Head, Method::Options]);
+            },
+            _ => panic!("405 is expected"),
+        }
+        assert!(call(&router, Method::Get, "/api/v2").is_err());
+    }
+
+    #[test]
+    fn named_routes() {
+        let mut router = Router::new();
+        router.get("/", Echo).name("root");
+        router.scope("/users/:user", |r| {
+            r.get("/", Echo).name("user");
+            r.get("/issues/:id", Echo).name("issue");
+        });
+
+        let urls = router.url_map();
+        assert_eq!(urls.url_for("root", &[]), Ok("/".to_string()));
+        assert_eq!(urls.url_for("user", &[("user", "foo")]), Ok("/users/foo".to_string()));
+        assert_eq!(urls.url_for("issue", &[("user", "foo bar"), ("id", "3")]),
+                   Ok("/users/foo%20bar/issues/3".to_string()));
+        assert!(urls.url_for("issue", &[("id", "3")]).is_err());
+        assert!(urls.url_for("unknown", &[]).is_err());
+    }
+
+    #[test]
+    fn static_over_param() {
+        let mut router = Router::new();
+        router.get("/users/:id", Echo);
+        router.get("/users/new", text("new"));
+
+        let resp = call(&router, Method::Get, "/users/new").ok().unwrap();
+        assert_eq!(body(resp), "new");
+        let rr = router.recognize(&Method::Get, "/users/3").unwrap();
+        assert_eq!(rr.param("id"), Some("3"));
+        assert!(router.recognize(&Method::Post, "/users/3").is_none());
+    }
+
+    struct Typed;
+
+    impl Handler<App> for Typed {
+        fn handle(&self, _app: Arc<App>, req: Request) -> HandlerResult {
+            match req.param_as::<u8>("id") {
+                Ok(id) => Response::text(format!("id={}", id)).render(),
+                Err(err) => err.render(),
+            }
+        }
+    }
+
+    #[test]
+    fn typed_params() {
+        let mut router = Router::new();
+        router.get("/users/:id<u64>", Typed);
+        router.get("/posts/:id", Typed);
+
+        let resp = call(&router, Method::Get, "/users/3").ok().unwrap();
+        assert_eq!(body(resp), "id=3");
+        match call(&router, Method::Get, "/users/abc") {
+            Err(ZirconError::Status(StatusCode::NotFound)) => (),
+            _ => panic!("404 is expected"),
+        }
+        match call(&router, Method::Get, "/posts/300") {
+            Err(ZirconError::StringError(StatusCode::BadRequest, _)) => (),
+            _ => panic!("400 is expected"),
+        }
+    }
+
+    struct Name;
+
+    impl Handler<App> for Name {
+        fn handle(&self, _app: Arc<App>, req: Request) -> HandlerResult {
+            Response::text(format!("{} {}", req.param("name").unwrap(), req.raw_param("name").unwrap())).render()
+        }
+    }
+
+    #[test]
+    fn decoded_params() {
+        let mut router = Router::new();
+        router.get("/users/:name", Name);
+
+        let resp = call(&router, Method::Get, "/users/%E6%97%A5%E6%9C%AC").ok().unwrap();
+        assert_eq!(body(resp), "日本 %E6%97%A5%E6%9C%AC");
+        match call(&router, Method::Get, "/users/%FF") {
+            Err(ZirconError::StringError(StatusCode::BadRequest, ref message)) => {
+                assert_eq!(message, "param name is not valid UTF-8");
+            },
+            _ => panic!("400 is expected"),
+        }
+    }
+
+    struct Rest;
+
+    impl Handler<App> for Rest {
+        fn handle(&self, _app: Arc<App>, req: Request) -> HandlerResult {
+            Response::text(req.param("rest").unwrap()).render()
+        }
+    }
+
+    #[test]
+    fn named_tail() {
+        let mut api = Router::new();
+        api.get("/files/*rest", Rest);
+        let mut router = Router::new();
+        router.get("/static/**:rest", Rest);
+        router.mount("/api", api);
+
+        let resp = call(&router, Method::Get, "/static/css/a%20b.css").ok().unwrap();
+        assert_eq!(body(resp), "css/a b.css");
+        let resp = call(&router, Method::Get, "/api/files/x/y").ok().unwrap();
+        assert_eq!(body(resp), "x/y");
+    }
+
+    #[test]
+    fn fallback() {
+        let mut router = Router::new();
+        router.get("/users", text("users"));
+        router.fallback(text("index"));
+
+        let resp = call(&router, Method::Get, "/foo/bar").ok().unwrap();
+        assert_eq!(body(resp), "index");
+        match call(&router, Method::Post, "/users") {
+            Err(ZirconError::MethodNotAllowed(_)) => (),
+            _ => panic!("405 is expected"),
+        }
+    }
+
+    #[test]
+    fn trailing_slash() {
+        let mut api = Router::new();
+        api.get("/items/", text("items"));
+        let mut router = Router::new();
+        router.get("/users", text("users"));
+        router.mount("/api", api);
+
+        match call(&router, Method::Get, "/users/") {
+            Err(ZirconError::Status(StatusCode::NotFound)) => (),
+            _ => panic!("404 is expected"),
+        }
+
+        router.trailing_slash(TrailingSlash::MovedPermanently);
+        let resp = call(&router, Method::Get, "/users/?page=2").ok().unwrap();
+        assert_eq!(resp.origin.status(), StatusCode::MovedPermanently);
+        assert_eq!(resp.origin.headers().get_raw("location").unwrap().one(), Some(&b"/users?page=2"[..]));
+
+        router.trailing_slash(TrailingSlash::PermanentRedirect);
+        let resp = call(&router, Method::Post, "/api/items").ok().unwrap();
+        assert_eq!(resp.origin.status(), StatusCode::PermanentRedirect);
+        assert_eq!(resp.origin.headers().get_raw("location").unwrap().one(), Some(&b"/api/items/"[..]));
+
+        match call(&router, Method::Get, "/other/") {
+            Err(ZirconError::Status(StatusCode::NotFound)) => (),
+            _ => panic!("404 is expected"),
+        }
+    }
+
+    #[test]
+    fn introspection() {
+        let mut issues = Router::new();
+        issues.get("/issues/:id<u64>", Echo).name("issue");
+        issues.any("/", Echo);
+        let mut router = Router::new();
+        router.add_route_with_methods(&[Method::Get, Method::Post], "/users/:id", Echo);
+        router.mount("/repos/:repo", issues);
+        router.get("/users/{name}", Echo);
+
+        let routes = router.routes();
+        assert_eq!(routes.len(), 4);
+        assert_eq!(routes[0].methods, Some(vec![Method::Get, Method::Post]));
+        assert_eq!(routes[1].pattern, "/repos/:repo/issues/:id<u64>");
+        assert_eq!(routes[1].name, Some("issue".to_string()));
+        assert_eq!(routes[1].params, vec!["repo", "id"]);
+        assert_eq!(routes[2].methods, None);
+        assert_eq!(routes[2].pattern, "/repos/:repo");
+
+        assert_eq!(router.route_table().lines().count(), 4);
+
+        let conflicts = router.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].first.pattern, "/users/:id");
+        assert_eq!(conflicts[0].second.pattern, "/users/{name}");
+        assert_eq!(conflicts[0].methods, Some(vec![Method::Get]));
+    }
+
+    struct Tag(&'static str);
+
+    impl Middleware<App> for Tag {
+        fn after(&self, _app: &Arc<App>, resp: &mut Response) {
+            let tags = match resp.origin.headers().get_raw("x-tags").and_then(|x| x.one()) {
+                Some(x) => format!("{},{}", String::from_utf8_lossy(x), self.0),
+                None => self.0.to_string(),
+            };
+            resp.origin.headers_mut().set_raw("x-tags", tags);
+        }
+    }
+
+    #[test]
+    fn scoped_middlewares() {
+        let mut router = Router::new();
+        router.middleware(Tag("root"));
+        router.scope("/admin", |r| {
+            r.middleware(Tag("admin"));
+            r.get("/users", text("users"));
+        });
+        router.get("/", text("top"));
+
+        let tags = |resp: &Response| resp.origin.headers().get_raw("x-tags").and_then(|x| x.one()).map(|x| x.to_vec());
+        let resp = call(&router, Method::Get, "/admin/users").ok().unwrap();
+        // The inner middleware runs `after` first.
+        assert_eq!(tags(&resp), Some(b"admin,root".to_vec()));
+        let resp = call(&router, Method::Get, "/").ok().unwrap();
+        assert_eq!(tags(&resp), Some(b"root".to_vec()));
+        // HEAD by GET routes goes through them too.
+        let resp = call(&router, Method::Head, "/admin/users").ok().unwrap();
+        assert_eq!(tags(&resp), Some(b"admin,root".to_vec()));
     }
 }
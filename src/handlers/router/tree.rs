@@ -0,0 +1,482 @@
+use std::collections::HashMap;
+
+use super::constraint::{self, Constraint};
+use super::matcher::{self, Matcher};
+
+/// Segment is one `/`-separated part of a route pattern.
+enum Segment<'a> {
+    Static(&'a str),
+    /// `:name`, `:name<constraint>`, `{name}` or `{name:constraint}`
+    Param(&'a str, Option<&'a str>),
+    /// A segment mixing text and params or wildcards, like `:name.json`.
+    Pattern(&'a str),
+    /// `*`
+    Wildcard,
+    /// `**`, or a named tail `*name` or `**:name`
+    CatchAll(Option<&'a str>),
+}
+
+impl<'a> Segment<'a> {
+    fn parse(s: &'a str) -> Segment<'a> {
+        if s == "*" {
+            Segment::Wildcard
+        } else if s == "**" {
+            Segment::CatchAll(None)
+        } else if let Some((name, len)) = constraint::parse_tail(s) {
+            if len == s.len() { Segment::CatchAll(Some(name)) } else { Segment::Pattern(s) }
+        } else if let Some((name, constraint)) = whole_param(s) {
+            Segment::Param(name, constraint)
+        } else if s.contains(':') || s.contains('*') || s.contains('{') {
+            Segment::Pattern(s)
+        } else {
+            Segment::Static(s)
+        }
+    }
+}
+
+/// Returns the name and the constraint if `s` is just one param placeholder.
+fn whole_param(s: &str) -> Option<(&str, Option<&str>)> {
+    match constraint::parse_param(s) {
+        Some((name, constraint, len)) if len == s.len() => Some((name, constraint)),
+        _ => None,
+    }
+}
+
+/// Splits `path` into segments with their offsets. `/` is one empty segment.
+/// None is returned if `path` doesn't start with `/`.
+fn split(path: &str) -> Option<Vec<(usize, &str)>> {
+    if !path.starts_with('/') {
+        return None;
+    }
+
+    let mut segments = Vec::new();
+    let mut start = 1;
+    for (i, b) in path.bytes().enumerate().skip(1) {
+        if b == b'/' {
+            segments.push((start, &path[start..i]));
+            start = i + 1;
+        }
+    }
+    segments.push((start, &path[start..]));
+    Some(segments)
+}
+
+/// Params captured while walking the tree, borrowed from the tree and the path.
+pub type Captures<'t, 'p> = [(&'t str, &'p str)];
+
+/// Node is a node of the route tree, one per segment.
+///
+/// Routes are tried in this order at each segment: static text, mount points, constrained params,
+/// segments mixing text and params, `:name`, `*`, and `**`. Routes of the same kind are tried in
+/// the order they are added. If a branch doesn't reach a route, the next one is tried.
+pub struct Node<T> {
+    /// Routes ending here.
+    leaves: Vec<T>,
+    /// Routers mounted here, which take the rest of the path.
+    mounts: Vec<T>,
+    statics: HashMap<String, Node<T>>,
+    /// Constrained params come first, so that they are tried before unconstrained ones.
+    params: Vec<(String, Option<Constraint>, Node<T>)>,
+    patterns: Vec<(Matcher, Node<T>)>,
+    wildcard: Option<Box<Node<T>>>,
+    /// `**` routes, with the param name of the tail if it is named.
+    catch_all: Vec<(Option<String>, T)>,
+    /// Routes the tree can't represent, e.g. `**` in the middle. Tried with regex after the tree.
+    /// Only the root has them.
+    tails: Vec<(Matcher, T)>,
+}
+
+impl<T> Node<T> {
+    pub fn new() -> Node<T> {
+        Node {
+            leaves: Vec::new(),
+            mounts: Vec::new(),
+            statics: HashMap::new(),
+            params: Vec::new(),
+            patterns: Vec::new(),
+            wildcard: None,
+            catch_all: Vec::new(),
+            tails: Vec::new(),
+        }
+    }
+
+    fn child(&mut self, segment: Segment) -> &mut Node<T> {
+        match segment {
+            Segment::Static(s) => self.statics.entry(s.to_string()).or_insert_with(Node::new),
+            Segment::Param(name, constraint) => {
+                let same = |&(ref n, ref c, _): &(String, Option<Constraint>, Node<T>)| {
+                    n == name && c.as_ref().map(|c| c.source()) == constraint
+                };
+                let pos = match self.params.iter().position(same) {
+                    Some(x) => x,
+                    None => {
+                        let pos = match constraint {
+                            Some(_) => self.params.iter().position(|x| x.1.is_none()).unwrap_or(self.params.len()),
+                            None => self.params.len(),
+                        };
+                        self.params.insert(pos, (name.to_string(), constraint.map(Constraint::new), Node::new()));
+                        pos
+                    },
+                };
+                &mut self.params[pos].2
+            },
+            Segment::Pattern(s) => {
+                let matcher = Matcher::from(s);
+                let pos = match self.patterns.iter().position(|&(ref m, _)| m.is_equivalent(&matcher)) {
+                    Some(x) => x,
+                    None => {
+                        self.patterns.push((matcher, Node::new()));
+                        self.patterns.len() - 1
+                    },
+                };
+                &mut self.patterns[pos].1
+            },
+            Segment::Wildcard => &mut **self.wildcard.get_or_insert_with(|| Box::new(Node::new())),
+            Segment::CatchAll(_) => unreachable!(),
+        }
+    }
+
+    /// Adds a route matching `pattern` exactly. Panics if a constraint in it contains `/`.
+    pub fn insert(&mut self, pattern: &str, value: T) {
+        constraint::check_constraints(pattern);
+        let segments: Vec<&str> = match split(pattern) {
+            Some(x) => x.into_iter().map(|(_, s)| s).collect(),
+            None => {
+                self.tails.push((pattern.into(), value));
+                return;
+            },
+        };
+        // `**` and named tails are in the tree only as the whole last segment.
+        let catch_all_pos = segments.iter().position(|s| constraint::has_tail(s));
+        if let Some(pos) = catch_all_pos {
+            let is_whole = match Segment::parse(segments[pos]) {
+                Segment::CatchAll(_) => true,
+                _ => false,
+            };
+            if pos + 1 != segments.len() || !is_whole {
+                self.tails.push((pattern.into(), value));
+                return;
+            }
+        }
+
+        let mut node = self;
+        for s in segments {
+            let segment = Segment::parse(s);
+            if let Segment::CatchAll(name) = segment {
+                node.catch_all.push((name.map(|x| x.to_string()), value));
+                return;
+            }
+            node = {node}.child(segment);
+        }
+        node.leaves.push(value);
+    }
+
+    /// Adds a router mounted at `prefix`. `prefix` must not end with `/` and must not have `**` or a named tail.
+    pub fn insert_mount(&mut self, prefix: &str, value: T) {
+        assert!(!constraint::has_tail(prefix), "mount point cannot have ** or a named tail: {}", prefix);
+        constraint::check_constraints(prefix);
+
+        let segments: Vec<&str> = match split(prefix) {
+            Some(x) => x.into_iter().map(|(_, s)| s).collect(),
+            None if prefix.is_empty() => Vec::new(),
+            None => panic!("mount point must start with /: {}", prefix),
+        };
+
+        let mut node = self;
+        for s in segments {
+            node = {node}.child(Segment::parse(s));
+        }
+        node.mounts.push(value);
+    }
+
+    /// Calls `f` with the routes matching `path` in precedence order, until `f` returns true.
+    /// For mounted routers, the rest of the path is passed too.
+    /// Returns true if `f` returned true.
+    pub fn walk<'t, 'p, F>(&'t self, path: &'p str, f: &mut F) -> bool
+    where F: FnMut(&'t T, &Captures<'t, 'p>, Option<&'p str>) -> bool {
+        if let Some(segments) = split(path) {
+            let mut captures = Vec::new();
+            if self.walk_segments(path, &segments, &mut captures, f) {
+                return true;
+            }
+        }
+
+        for &(ref matcher, ref value) in &self.tails {
+            if let Some(captures) = matcher.captures(path) {
+                if f(value, &captures[..], None) {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    fn walk_segments<'t, 'p, F>(&'t self, path: &'p str, segments: &[(usize, &'p str)],
+                                captures: &mut Vec<(&'t str, &'p str)>, f: &mut F) -> bool
+    where F: FnMut(&'t T, &Captures<'t, 'p>, Option<&'p str>) -> bool {
+        let (start, segment) = match segments.first() {
+            Some(&x) => x,
+            None => {
+                for value in &self.leaves {
+                    if f(value, &captures[..], None) {
+                        return true;
+                    }
+                }
+                for value in &self.mounts {
+                    if f(value, &captures[..], Some("")) {
+                        return true;
+                    }
+                }
+                return false;
+            },
+        };
+        let rest = &segments[1..];
+
+        if let Some(child) = self.statics.get(segment) {
+            if child.walk_segments(path, rest, captures, f) {
+                return true;
+            }
+        }
+
+        for value in &self.mounts {
+            // The rest of the path includes the `/` before this segment.
+            if f(value, &captures[..], Some(&path[(start - 1)..])) {
+                return true;
+            }
+        }
+
+        let is_segment = matcher::is_segment(segment);
+        for &(ref name, ref constraint, ref child) in &self.params {
+            match *constraint {
+                Some(ref c) if is_segment && c.matches(segment) => (),
+                _ => continue,
+            }
+            captures.push((name.as_str(), segment));
+            if child.walk_segments(path, rest, captures, f) {
+                return true;
+            }
+            captures.pop();
+        }
+
+        for &(ref pattern, ref child) in &self.patterns {
+            if let Some(params) = pattern.captures(segment) {
+                let len = captures.len();
+                captures.extend(params);
+                if child.walk_segments(path, rest, captures, f) {
+                    return true;
+                }
+                captures.truncate(len);
+            }
+        }
+
+        for &(ref name, ref constraint, ref child) in &self.params {
+            if constraint.is_some() || segment.is_empty() || !is_segment {
+                continue;
+            }
+            captures.push((name.as_str(), segment));
+            if child.walk_segments(path, rest, captures, f) {
+                return true;
+            }
+            captures.pop();
+        }
+
+        if let Some(ref child) = self.wildcard {
+            if is_segment && child.walk_segments(path, rest, captures, f) {
+                return true;
+            }
+        }
+
+        if !self.catch_all.is_empty() && path[start..].split('/').all(matcher::is_segment) {
+            for &(ref name, ref value) in &self.catch_all {
+                if let Some(ref name) = *name {
+                    captures.push((name.as_str(), &path[start..]));
+                }
+                if f(value, &captures[..], None) {
+                    return true;
+                }
+                if name.is_some() {
+                    captures.pop();
+                }
+            }
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the values matching `path` in order, with their params.
+    fn matches(tree: &Node<&'static str>, path: &str) -> Vec<(String, Vec<(String, String)>)> {
+        let mut result = Vec::new();
+        tree.walk(path, &mut |value, captures, _| {
+            let params = captures.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect();
+            result.push((value.to_string(), params));
+            false
+        });
+        result
+    }
+
+    fn first(tree: &Node<&'static str>, path: &str) -> Option<String> {
+        matches(tree, path).into_iter().next().map(|(value, _)| value)
+    }
+
+    #[test]
+    fn split_path() {
+        assert_eq!(split("/"), Some(vec![(1, "")]));
+        assert_eq!(split("/foo/bar"), Some(vec![(1, "foo"), (5, "bar")]));
+        assert_eq!(split("/foo/"), Some(vec![(1, "foo"), (5, "")]));
+        assert_eq!(split("foo"), None);
+    }
+
+    #[test]
+    fn precedence() {
+        let mut tree = Node::new();
+        tree.insert("/**", "catch_all");
+        tree.insert("/users/*", "wildcard");
+        tree.insert("/users/:id", "param");
+        tree.insert("/users/new", "static");
+        tree.insert("/users/:id.json", "pattern");
+
+        assert_eq!(first(&tree, "/users/new"), Some("static".to_string()));
+        assert_eq!(first(&tree, "/users/3"), Some("param".to_string()));
+        assert_eq!(first(&tree, "/users/3.json"), Some("pattern".to_string()));
+        assert_eq!(first(&tree, "/users/a.b"), Some("param".to_string()));
+        assert_eq!(first(&tree, "/users/3/edit"), Some("catch_all".to_string()));
+
+        let all: Vec<String> = matches(&tree, "/users/new").into_iter().map(|(v, _)| v).collect();
+        assert_eq!(all, vec!["static", "param", "wildcard", "catch_all"]);
+        let all: Vec<String> = matches(&tree, "/users/3.json").into_iter().map(|(v, _)| v).collect();
+        assert_eq!(all, vec!["pattern", "param", "wildcard", "catch_all"]);
+    }
+
+    #[test]
+    fn backtrack() {
+        let mut tree = Node::new();
+        tree.insert("/users/new/edit", "static");
+        tree.insert("/users/:id/show", "param");
+
+        let m = matches(&tree, "/users/new/show");
+        assert_eq!(m, vec![("param".to_string(), vec![("id".to_string(), "new".to_string())])]);
+    }
+
+    #[test]
+    fn params() {
+        let mut tree = Node::new();
+        tree.insert("/:user/issues/:id", "issue");
+        tree.insert("/:name", "name");
+
+        let m = matches(&tree, "/foo/issues/3");
+        assert_eq!(m, vec![("issue".to_string(), vec![("user".to_string(), "foo".to_string()),
+                                                      ("id".to_string(), "3".to_string())])]);
+        let m = matches(&tree, "/bar");
+        assert_eq!(m, vec![("name".to_string(), vec![("name".to_string(), "bar".to_string())])]);
+        assert!(matches(&tree, "/foo/issues").is_empty());
+    }
+
+    #[test]
+    fn catch_all() {
+        let mut tree = Node::new();
+        tree.insert("/:issue/**", "tail");
+
+        assert!(matches(&tree, "/foo").is_empty());
+        assert_eq!(first(&tree, "/foo/"), Some("tail".to_string()));
+        assert_eq!(first(&tree, "/foo/bar/baz"), Some("tail".to_string()));
+    }
+
+    #[test]
+    fn tails() {
+        let mut tree = Node::new();
+        tree.insert("/a/**/z", "middle");
+
+        assert_eq!(first(&tree, "/a/b/c/z"), Some("middle".to_string()));
+        assert!(matches(&tree, "/a/b/c").is_empty());
+    }
+
+    #[test]
+    fn mounts() {
+        let mut tree = Node::new();
+        tree.insert_mount("/api", "api");
+        tree.insert("/api/version", "version");
+
+        let mut rests = Vec::new();
+        tree.walk("/api/users", &mut |value, _, rest| {
+            rests.push((value.to_string(), rest.map(|x| x.to_string())));
+            false
+        });
+        assert_eq!(rests, vec![("api".to_string(), Some("/users".to_string()))]);
+
+        assert_eq!(first(&tree, "/api/version"), Some("version".to_string()));
+        assert_eq!(first(&tree, "/api"), Some("api".to_string()));
+        assert!(matches(&tree, "/apis").is_empty());
+    }
+
+    #[test]
+    fn constrained_params() {
+        let mut tree = Node::new();
+        tree.insert("/users/:name", "name");
+        tree.insert("/users/:id<u64>", "id");
+        tree.insert("/posts/{slug:[a-z-]+}", "slug");
+        tree.insert("/posts/{id:int}.json", "json");
+
+        assert_eq!(first(&tree, "/users/3"), Some("id".to_string()));
+        assert_eq!(first(&tree, "/users/abc"), Some("name".to_string()));
+        assert_eq!(first(&tree, "/posts/hello-world"), Some("slug".to_string()));
+        assert_eq!(first(&tree, "/posts/-3.json"), Some("json".to_string()));
+        assert!(matches(&tree, "/posts/Hello").is_empty());
+        // Params don't match an empty segment.
+        assert!(matches(&tree, "/users/").is_empty());
+    }
+
+    #[test]
+    fn typed_ranges() {
+        let mut tree = Node::new();
+        tree.insert("/items/{id:u8}.json", "json");
+        tree.insert("/items/{id:u16}.json", "wide");
+        tree.insert("/files/*path/:rev<u8>", "rev");
+
+        assert_eq!(first(&tree, "/items/255.json"), Some("json".to_string()));
+        // Out of range for u8, but the next pattern accepts it.
+        assert_eq!(first(&tree, "/items/999.json"), Some("wide".to_string()));
+        assert!(matches(&tree, "/items/70000.json").is_empty());
+
+        let m = matches(&tree, "/files/a/b/7");
+        assert_eq!(m, vec![("rev".to_string(), vec![("path".to_string(), "a/b".to_string()),
+                                                    ("rev".to_string(), "7".to_string())])]);
+        assert!(matches(&tree, "/files/a/b/999").is_empty());
+    }
+
+    #[test]
+    fn pchar_segments() {
+        let mut tree = Node::new();
+        tree.insert("/users/:name", "name");
+        tree.insert("/files/**", "files");
+
+        assert_eq!(first(&tree, "/users/a~b@c:d+e"), Some("name".to_string()));
+        assert_eq!(first(&tree, "/users/%E6%97%A5"), Some("name".to_string()));
+        assert_eq!(first(&tree, "/users/日本"), Some("name".to_string()));
+        assert!(matches(&tree, "/users/a%2").is_empty());
+        assert!(matches(&tree, "/users/a b").is_empty());
+        assert_eq!(first(&tree, "/files/a/b~c/%20"), Some("files".to_string()));
+        assert!(matches(&tree, "/files/a/b c").is_empty());
+    }
+
+    #[test]
+    fn named_tails() {
+        let mut tree = Node::new();
+        tree.insert("/files/*rest", "files");
+        tree.insert("/static/**:path", "static");
+        tree.insert("/repos/*path/raw", "raw");
+
+        let m = matches(&tree, "/files/a/b.txt");
+        assert_eq!(m, vec![("files".to_string(), vec![("rest".to_string(), "a/b.txt".to_string())])]);
+        let m = matches(&tree, "/static/");
+        assert_eq!(m, vec![("static".to_string(), vec![("path".to_string(), "".to_string())])]);
+        let m = matches(&tree, "/repos/src/lib.rs/raw");
+        assert_eq!(m, vec![("raw".to_string(), vec![("path".to_string(), "src/lib.rs".to_string())])]);
+        assert!(matches(&tree, "/files").is_empty());
+    }
+}
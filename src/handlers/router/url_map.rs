@@ -0,0 +1,118 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use url::percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+
+use super::constraint;
+
+/// Fills `:param` segments of a route pattern with `params`.
+/// Values are percent-encoded. Unnamed wildcards can't be filled, so they are an error.
+fn build_url(pattern: &str, params: &[(&str, &str)]) -> Result<String, String> {
+    let mut url = String::new();
+    let mut pos = 0;
+
+    while pos < pattern.len() {
+        let rest = &pattern[pos..];
+        if let Some((name, len)) = constraint::parse_tail(rest) {
+            // The tail keeps `/`, so each segment is encoded.
+            match params.iter().find(|&&(k, _)| k == name) {
+                Some(&(_, v)) => {
+                    for (i, segment) in v.split('/').enumerate() {
+                        if i > 0 {
+                            url.push('/');
+                        }
+                        url.extend(utf8_percent_encode(segment, PATH_SEGMENT_ENCODE_SET));
+                    }
+                },
+                None => return Err(format!("param {} is missing for {}", name, pattern)),
+            }
+            pos += len;
+            continue;
+        }
+        if rest.starts_with('*') {
+            return Err(format!("cannot build url for wildcard route {}", pattern));
+        }
+
+        if let Some((name, _, len)) = constraint::parse_param(rest) {
+            match params.iter().find(|&&(k, _)| k == name) {
+                Some(&(_, v)) => url.extend(utf8_percent_encode(v, PATH_SEGMENT_ENCODE_SET)),
+                None => return Err(format!("param {} is missing for {}", name, pattern)),
+            }
+            pos += len;
+            continue;
+        }
+
+        let c = rest.chars().next().unwrap();
+        url.push(c);
+        pos += c.len_utf8();
+    }
+
+    if url.is_empty() {
+        url.push('/');
+    }
+    Ok(url)
+}
+
+/// UrlMap makes URLs of named routes. Take it by `Router::url_map()` after adding every route.
+///
+/// It is cheap to clone, so it can be kept in the app (see `ZirconDefaultApp::with_url_map`)
+/// and registered to `HandlebarsEngine::register_url_for`.
+#[derive(Clone, Default)]
+pub struct UrlMap {
+    patterns: Arc<HashMap<String, String>>,
+}
+
+impl UrlMap {
+    pub fn new(patterns: HashMap<String, String>) -> UrlMap {
+        UrlMap {
+            patterns: Arc::new(patterns),
+        }
+    }
+
+    /// Returns the path of the route named `name`, e.g. `url_for("issue", &[("id", "3")])`
+    /// for `/issues/:id` returns `/issues/3`.
+    pub fn url_for(&self, name: &str, params: &[(&str, &str)]) -> Result<String, String> {
+        match self.patterns.get(name) {
+            Some(pattern) => build_url(pattern, params),
+            None => Err(format!("no route is named {}", name)),
+        }
+    }
+
+    /// Returns the full pattern of the route named `name`, including mount prefixes.
+    pub fn pattern(&self, name: &str) -> Option<&str> {
+        self.patterns.get(name).map(|x| x.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_url_simple() {
+        assert_eq!(build_url("/", &[]), Ok("/".to_string()));
+        assert_eq!(build_url("", &[]), Ok("/".to_string()));
+        assert_eq!(build_url("/foo/bar", &[]), Ok("/foo/bar".to_string()));
+    }
+
+    #[test]
+    fn build_url_params() {
+        assert_eq!(build_url("/:user/issues/:id", &[("id", "3"), ("user", "foo")]),
+                   Ok("/foo/issues/3".to_string()));
+        assert_eq!(build_url("/files/:name", &[("name", "a b/c?")]),
+                   Ok("/files/a%20b%2Fc%3F".to_string()));
+        assert_eq!(build_url("/users/:name", &[("name", "日本")]),
+                   Ok("/users/%E6%97%A5%E6%9C%AC".to_string()));
+
+        assert_eq!(build_url("/users/:id<u64>/posts/{slug:[a-z]+}", &[("id", "3"), ("slug", "abc")]),
+                   Ok("/users/3/posts/abc".to_string()));
+
+        assert!(build_url("/:user", &[]).is_err());
+        assert!(build_url("/public/**", &[]).is_err());
+
+        assert_eq!(build_url("/files/*rest", &[("rest", "a b/c.txt")]),
+                   Ok("/files/a%20b/c.txt".to_string()));
+        assert_eq!(build_url("/files/**:rest", &[("rest", "a/b")]), Ok("/files/a/b".to_string()));
+        assert!(build_url("/files/*rest", &[]).is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use handlers::router::RouteResult;
+use prelude::*;
+
+/// The first label of a wildcard host, `*` or `:name`.
+struct WildcardHost {
+    /// The param name of the label.
+    param: String,
+    /// The rest of the host after the label, like `.example.com`.
+    suffix: String,
+}
+
+impl WildcardHost {
+    /// Returns the label if `host` is one label followed by the suffix.
+    fn matches<'a>(&self, host: &'a str) -> Option<&'a str> {
+        if host.len() <= self.suffix.len() || !host.ends_with(&self.suffix) {
+            return None;
+        }
+        let label = &host[..(host.len() - self.suffix.len())];
+        if label.contains('.') { None } else { Some(label) }
+    }
+}
+
+/// VirtualHostHandler dispatches requests by the host name the client requested.
+///
+/// The host is taken from `Request::host()`, so Forwarded and X-Forwarded-Host are respected
+/// when the server is configured so. Host names are compared case-insensitively, without port.
+///
+/// ```ignore
+/// let mut vhosts = VirtualHostHandler::new();
+/// vhosts.add_host("example.com", site);
+/// vhosts.add_host(":tenant.example.com", tenants);  // req.param("tenant")
+/// vhosts.add_host("*.example.org", other);          // req.param("subdomain")
+/// ```
+pub struct VirtualHostHandler<A: ZirconApp> {
+    hosts: HashMap<String, Box<Handler<A>>>,
+    wildcards: Vec<(WildcardHost, Box<Handler<A>>)>,
+    default: Option<Box<Handler<A>>>,
+}
+
+impl<A: ZirconApp> VirtualHostHandler<A> {
+    pub fn new() -> VirtualHostHandler<A> {
+        VirtualHostHandler {
+            hosts: HashMap::new(),
+            wildcards: Vec::new(),
+            default: None,
+        }
+    }
+
+    /// Adds a handler for `host`. The first label can be a wildcard matching one label:
+    /// `*.example.com` exposes it as the param `subdomain`, and `:name.example.com` as `name`.
+    /// Exact host names win over wildcards. Wildcards are tried in the order they are added.
+    pub fn add_host<H: Handler<A>>(&mut self, host: &str, handler: H) -> &mut VirtualHostHandler<A> {
+        let host = host.to_ascii_lowercase();
+        let (label, suffix) = match host.find('.') {
+            Some(pos) => (&host[..pos], &host[pos..]),
+            None => (&host[..], ""),
+        };
+
+        let param = if label == "*" {
+            "subdomain"
+        } else if label.starts_with(':') && label.len() > 1 {
+            &label[1..]
+        } else {
+            self.hosts.insert(host.clone(), Box::new(handler));
+            return self;
+        };
+
+        assert!(!suffix.is_empty(), "wildcard host needs a domain: {}", host);
+        self.wildcards.push((WildcardHost {
+            param: param.to_string(),
+            suffix: suffix.to_string(),
+        }, Box::new(handler)));
+        self
+    }
+
+    /// Sets the handler for requests no host matches, including ones without Host.
+    /// Without it, 404 Not Found is returned.
+    pub fn default<H: Handler<A>>(&mut self, handler: H) -> &mut VirtualHostHandler<A> {
+        self.default = Some(Box::new(handler));
+        self
+    }
+}
+
+impl<A: ZirconApp> Handler<A> for VirtualHostHandler<A> {
+    fn handle(&self, app: Arc<A>, mut req: Request) -> HandlerResult {
+        let host = req.host().map(|x| x.trim_right_matches('.').to_ascii_lowercase());
+
+        if let Some(host) = host {
+            if let Some(handler) = self.hosts.get(&host) {
+                return handler.handle(app, req);
+            }
+
+            for &(ref wildcard, ref handler) in &self.wildcards {
+                let label = match wildcard.matches(&host) {
+                    Some(x) => x.to_string(),
+                    None => continue,
+                };
+                let mut params = req.header.params.take().unwrap_or_default();
+                params.merge(RouteResult::new(vec![(wildcard.param.clone(), label)]));
+                req.set_params(Some(params));
+                return handler.handle(app, req);
+            }
+        }
+
+        match self.default {
+            Some(ref handler) => handler.handle(app, req),
+            None => ZirconError::render_error_status(StatusCode::NotFound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{Future, Stream};
+    use hyper::Method;
+    use hyper::server::Request as HyperRequest;
+
+    type App = ZirconDefaultApp<()>;
+
+    struct Echo(&'static str);
+
+    impl Handler<App> for Echo {
+        fn handle(&self, _app: Arc<App>, req: Request) -> HandlerResult {
+            let param = req.header.params.as_ref().and_then(|p| p.param("subdomain").or(p.param("tenant")));
+            Response::text(format!("{} {:?}", self.0, param)).render()
+        }
+    }
+
+    fn call(handler: &VirtualHostHandler<App>, host: &str) -> Result<String, ZirconError> {
+        let app = Arc::new(ZirconDefaultApp::<()>::from_config(ZirconConfig::dev()));
+        let mut origin = HyperRequest::new(Method::Get, "/".parse().unwrap());
+        if !host.is_empty() {
+            origin.headers_mut().set_raw("Host", host.to_string());
+        }
+        let resp = handler.handle(app, Request::from_internal(origin)).wait()?;
+        let body = resp.origin.body().concat2().wait().unwrap();
+        Ok(String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn virtual_hosts() {
+        let mut handler = VirtualHostHandler::new();
+        handler.add_host("example.com", Echo("site"))
+            .add_host("admin.example.com", Echo("admin"))
+            .add_host(":tenant.example.com", Echo("tenant"))
+            .add_host("*.example.org", Echo("org"));
+
+        assert_eq!(call(&handler, "example.com").ok(), Some("site None".to_string()));
+        assert_eq!(call(&handler, "Example.COM:8080").ok(), Some("site None".to_string()));
+        assert_eq!(call(&handler, "admin.example.com").ok(), Some("admin None".to_string()));
+        assert_eq!(call(&handler, "foo.example.com").ok(), Some("tenant Some(\"foo\")".to_string()));
+        assert_eq!(call(&handler, "bar.example.org").ok(), Some("org Some(\"bar\")".to_string()));
+
+        for host in &["a.b.example.com", "example.org", "other.com", ""] {
+            match call(&handler, host) {
+                Err(ZirconError::Status(StatusCode::NotFound)) => (),
+                _ => panic!("404 is expected for {}", host),
+            }
+        }
+
+        handler.default(Echo("default"));
+        assert_eq!(call(&handler, "other.com").ok(), Some("default None".to_string()));
+        assert_eq!(call(&handler, "").ok(), Some("default None".to_string()));
+    }
+}
@@ -5,25 +5,41 @@ pub extern crate futures;
 pub extern crate futures_cpupool;
 pub extern crate handlebars;
 #[macro_use] pub extern crate hyper;
+extern crate libc;
 #[macro_use] extern crate log;
 extern crate regex;
+extern crate rustls;
 extern crate typemap;
 pub extern crate url;
 extern crate walkdir;
+extern crate webpki;
 extern crate net2;
 extern crate serde;
 extern crate serde_json;
 extern crate tokio_core;
+extern crate tokio_io;
+extern crate tokio_rustls;
+extern crate tokio_signal;
+extern crate tokio_uds;
 
+mod access_log;
 mod app;
 mod config;
+mod connection;
 mod error;
+mod listener;
 mod request;
 mod response;
+mod shutdown;
+mod systemd;
+mod tls;
+mod unwind;
+mod upgrade;
 mod zircon;
 
 pub mod extensions;
 pub mod handlers;
+pub mod middleware;
 pub mod renderers;
 pub mod templates;
 
@@ -37,14 +53,19 @@ pub use hyper::server::Request as HyperRequest;
 pub use hyper::server::Response as HyperResponse;
 
 // Export zircon things
+pub use access_log::{AccessLog, LogFormat, RotatingFile};
 pub use app::ZirconApp;
 pub use app::ZirconDefaultApp;
 pub use config::Mode;
 pub use config::ZirconConfig;
 pub use error::ZirconError;
+pub use listener::{ListenAddr, UnixListenAddr};
 pub use handlers::router::Router;
-pub use request::Request;
+pub use request::{Cidr, ForwardedHeaders, Request};
 pub use response::Response;
+pub use shutdown::Shutdown;
+pub use tls::TlsConfig;
+pub use upgrade::UpgradeHandle;
 pub use templates::HandlebarsEngine;
 
 /// module prelude provides an easy access of zircon important modules to a user.
@@ -62,6 +83,7 @@ pub mod prelude {
     pub use hyper::StatusCode;
     pub use request::Query;
     pub use templates::HandlebarsEngine;
+    pub use zircon::Server;
     pub use zircon::Zircon;
 }
 
@@ -78,6 +100,8 @@ where F: Fn(Arc<A>, Request) -> HandlerResult + Send + Sync + 'static {
     }
 }
 
+/// ErrorHandler makes the response for a request whose handler failed with ZirconError,
+/// including `ZirconError::Panic` when the handler panicked.
 pub trait ErrorHandler<A>: Send + Sync + 'static {
     fn handle(&self, Arc<A>, &ZirconError) -> HandlerResult;
 }
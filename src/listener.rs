@@ -0,0 +1,262 @@
+use std::fs;
+use std::io;
+use std::net::{Ipv6Addr, SocketAddr, SocketAddrV6, TcpListener};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Arc;
+
+use net2::TcpBuilder;
+use net2::unix::UnixTcpBuilderExt;
+use rustls::ServerConfig;
+
+/// ListenAddr is an address for Zircon to listen on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ListenAddr {
+    addr: SocketAddr,
+    dual_stack: bool,
+    reuse_port: bool,
+}
+
+impl ListenAddr {
+    pub fn new(addr: SocketAddr) -> ListenAddr {
+        ListenAddr {
+            addr: addr,
+            dual_stack: false,
+            reuse_port: false,
+        }
+    }
+
+    /// Listens on `[::]:port` for both IPv6 and IPv4 clients.
+    /// IPv4 clients are seen as IPv4-mapped IPv6 addresses.
+    pub fn dual_stack(port: u16) -> ListenAddr {
+        let unspecified = Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0);
+        ListenAddr::new(SocketAddr::V6(SocketAddrV6::new(unspecified, port, 0, 0))).with_dual_stack(true)
+    }
+
+    /// When true, an IPv6 address accepts IPv4 connections too.
+    /// When false (default), an IPv6 address accepts IPv6 connections only,
+    /// so that `0.0.0.0` and `[::]` can be bound at the same time.
+    /// This has no effect on an IPv4 address.
+    pub fn with_dual_stack(mut self, b: bool) -> ListenAddr {
+        self.dual_stack = b;
+        self
+    }
+
+    /// When true, SO_REUSEPORT is set, so that another process can listen on the same port at the same time,
+    /// e.g. to hand over the port to a new process started without `UpgradeHandle`. When false (default),
+    /// binding fails if the port is already in use. `UpgradeHandle` passes the socket itself and doesn't need this.
+    pub fn with_reuse_port(mut self, b: bool) -> ListenAddr {
+        self.reuse_port = b;
+        self
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn is_dual_stack(&self) -> bool {
+        self.dual_stack
+    }
+
+    /// Creates a listening socket for this address.
+    pub fn bind(&self, backlog: i32) -> io::Result<TcpListener> {
+        let builder = match self.addr {
+            SocketAddr::V4(_) => TcpBuilder::new_v4()?,
+            SocketAddr::V6(_) => {
+                let builder = TcpBuilder::new_v6()?;
+                builder.only_v6(!self.dual_stack)?;
+                builder
+            },
+        };
+
+        // Same as std: a port in TIME_WAIT can be bound again on restart.
+        builder.reuse_address(true)?;
+        if self.reuse_port {
+            builder.reuse_port(true)?;
+        }
+        builder.bind(&self.addr)?;
+        builder.listen(backlog)
+    }
+}
+
+impl From<SocketAddr> for ListenAddr {
+    fn from(addr: SocketAddr) -> ListenAddr {
+        ListenAddr::new(addr)
+    }
+}
+
+/// UnixListenAddr is a Unix domain socket path for Zircon to listen on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnixListenAddr {
+    path: PathBuf,
+    mode: Option<u32>,
+}
+
+impl UnixListenAddr {
+    pub fn new<P: AsRef<Path>>(path: P) -> UnixListenAddr {
+        UnixListenAddr {
+            path: path.as_ref().to_path_buf(),
+            mode: None,
+        }
+    }
+
+    /// Sets the permission bits of the socket file, e.g. `0o660` to allow a proxy in the same group.
+    /// The socket file appears at the path with these bits already set.
+    /// When not set, the socket file is created following the process umask.
+    pub fn with_mode(mut self, mode: u32) -> UnixListenAddr {
+        self.mode = Some(mode);
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Creates a listening socket for this path.
+    ///
+    /// If a socket file is left at the path by a process that has exited, it is removed.
+    /// If another process is still listening on it, or the path is not a socket, AddrInUse is returned.
+    pub fn bind(&self) -> io::Result<UnixListener> {
+        match fs::symlink_metadata(&self.path) {
+            Ok(ref meta) if !meta.file_type().is_socket() => {
+                return Err(io::Error::new(io::ErrorKind::AddrInUse,
+                                          format!("{} exists and is not a socket", self.path.display())));
+            },
+            Ok(_) => {
+                match UnixStream::connect(&self.path) {
+                    Ok(_) => {
+                        return Err(io::Error::new(io::ErrorKind::AddrInUse,
+                                                  format!("{} is used by another process", self.path.display())));
+                    },
+                    Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                        info!("removing stale socket {}", self.path.display());
+                        fs::remove_file(&self.path)?;
+                    },
+                    Err(e) => return Err(e),
+                }
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+
+        let mode = match self.mode {
+            Some(x) => x,
+            None => return UnixListener::bind(&self.path),
+        };
+
+        // Connecting needs write permission on the socket file, so it is bound at a temporary path,
+        // and appears at the path only after the permission is set.
+        let mut tmp_name = self.path.file_name().map(|x| x.to_os_string()).unwrap_or_default();
+        tmp_name.push(format!(".{}.tmp", process::id()));
+        let tmp_path = self.path.with_file_name(tmp_name);
+        match fs::remove_file(&tmp_path) {
+            Ok(_) => (),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => return Err(e),
+        }
+
+        let listener = UnixListener::bind(&tmp_path)?;
+        let result = fs::set_permissions(&tmp_path, fs::Permissions::from_mode(mode))
+            .and_then(|_| fs::rename(&tmp_path, &self.path));
+        if let Err(e) = result {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+
+        Ok(listener)
+    }
+}
+
+impl<'a> From<&'a str> for UnixListenAddr {
+    fn from(path: &'a str) -> UnixListenAddr {
+        UnixListenAddr::new(path)
+    }
+}
+
+impl<'a> From<&'a Path> for UnixListenAddr {
+    fn from(path: &'a Path) -> UnixListenAddr {
+        UnixListenAddr::new(path)
+    }
+}
+
+impl From<PathBuf> for UnixListenAddr {
+    fn from(path: PathBuf) -> UnixListenAddr {
+        UnixListenAddr::new(path)
+    }
+}
+
+/// Listener is a bound socket that Zircon accepts connections from.
+pub enum Listener {
+    Tcp(TcpListener),
+    Tls(TcpListener, Arc<ServerConfig>),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    /// Wraps a listening stream socket, e.g. one inherited from the parent process.
+    /// Whether it is a TCP or Unix domain socket is detected from its address.
+    ///
+    /// This is unsafe because the caller must own `fd`.
+    pub unsafe fn from_raw_fd(fd: RawFd) -> io::Result<Listener> {
+        let tcp = TcpListener::from_raw_fd(fd);
+        if tcp.local_addr().is_ok() {
+            return Ok(Listener::Tcp(tcp));
+        }
+
+        let unix = UnixListener::from_raw_fd(tcp.into_raw_fd());
+        unix.local_addr()?;
+        Ok(Listener::Unix(unix))
+    }
+
+    pub fn as_raw_fd(&self) -> RawFd {
+        match *self {
+            Listener::Tcp(ref l) | Listener::Tls(ref l, _) => l.as_raw_fd(),
+            Listener::Unix(ref l) => l.as_raw_fd(),
+        }
+    }
+
+    pub fn try_clone(&self) -> io::Result<Listener> {
+        match *self {
+            Listener::Tcp(ref l) => l.try_clone().map(Listener::Tcp),
+            Listener::Tls(ref l, ref config) => l.try_clone().map(|l| Listener::Tls(l, config.clone())),
+            Listener::Unix(ref l) => l.try_clone().map(Listener::Unix),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn port_in_use() {
+        let listener = ListenAddr::new("127.0.0.1:0".parse().unwrap()).bind(16).unwrap();
+        let addr = listener.local_addr().unwrap();
+        assert_eq!(ListenAddr::new(addr).bind(16).err().map(|e| e.kind()), Some(io::ErrorKind::AddrInUse));
+
+        let a = ListenAddr::new("127.0.0.1:0".parse().unwrap()).with_reuse_port(true).bind(16).unwrap();
+        let addr = a.local_addr().unwrap();
+        assert!(ListenAddr::new(addr).with_reuse_port(true).bind(16).is_ok());
+    }
+
+    #[test]
+    fn unix_socket_mode() {
+        let path = env::temp_dir().join(format!("zircon-listener-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+
+        let listener = UnixListenAddr::new(&path).with_mode(0o600).bind().unwrap();
+        let meta = fs::symlink_metadata(&path).unwrap();
+        assert!(meta.file_type().is_socket());
+        assert_eq!(meta.permissions().mode() & 0o777, 0o600);
+        assert!(UnixStream::connect(&path).is_ok());
+
+        // The path is in use while the listener is alive.
+        assert_eq!(UnixListenAddr::new(&path).bind().err().map(|e| e.kind()), Some(io::ErrorKind::AddrInUse));
+        drop(listener);
+        fs::remove_file(&path).unwrap();
+    }
+}
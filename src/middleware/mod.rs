@@ -0,0 +1,200 @@
+use std::sync::Arc;
+
+use futures::Future;
+use prelude::*;
+
+/// Middleware runs code around a handler. Add it to a `Chain`, or to a `Router` by `Router::middleware`.
+///
+/// Every method has a default that does nothing, so implement only what is needed:
+/// `before` to inspect or reject a request, `after` to modify a response,
+/// and `around` to see both, e.g. to measure the time.
+pub trait Middleware<A: ZirconApp>: Send + Sync + 'static {
+    /// Called before the rest of the chain. Returning a result short-circuits the chain:
+    /// the handler and the inner middlewares are not called, and `after` of this middleware is skipped.
+    fn before(&self, _app: &Arc<A>, _req: &mut Request) -> Option<HandlerResult> {
+        None
+    }
+
+    /// Called with the response of the rest of the chain. Not called when it failed with ZirconError.
+    fn after(&self, _app: &Arc<A>, _resp: &mut Response) {}
+
+    /// Runs the rest of the chain by `next.run(app, req)`. It is called between `before` and `after`,
+    /// and it can modify the request or the HandlerResult future, or not call `next` at all.
+    fn around(&self, app: Arc<A>, req: Request, next: Next<A>) -> HandlerResult {
+        next.run(app, req)
+    }
+}
+
+/// Next is the rest of a middleware chain, ending with the handler.
+pub struct Next<A: ZirconApp> {
+    middlewares: Arc<Vec<Arc<Middleware<A>>>>,
+    index: usize,
+    handler: Arc<Handler<A>>,
+}
+
+impl<A: ZirconApp> Clone for Next<A> {
+    fn clone(&self) -> Next<A> {
+        Next {
+            middlewares: self.middlewares.clone(),
+            index: self.index,
+            handler: self.handler.clone(),
+        }
+    }
+}
+
+impl<A: ZirconApp> Next<A> {
+    /// Makes a chain calling `middlewares` in order, and `handler` at last.
+    pub fn new(middlewares: Arc<Vec<Arc<Middleware<A>>>>, handler: Arc<Handler<A>>) -> Next<A> {
+        Next {
+            middlewares: middlewares,
+            index: 0,
+            handler: handler,
+        }
+    }
+
+    /// Runs the rest of the chain.
+    pub fn run(self, app: Arc<A>, mut req: Request) -> HandlerResult {
+        let middleware = match self.middlewares.get(self.index) {
+            Some(x) => x.clone(),
+            None => return self.handler.handle(app, req),
+        };
+
+        if let Some(result) = middleware.before(&app, &mut req) {
+            return result;
+        }
+
+        let next = Next {
+            middlewares: self.middlewares,
+            index: self.index + 1,
+            handler: self.handler,
+        };
+        let result = middleware.around(app.clone(), req, next);
+        Box::new(result.map(move |mut resp| {
+            middleware.after(&app, &mut resp);
+            resp
+        }))
+    }
+}
+
+/// Chain wraps a handler with middlewares. The middleware added first runs outermost.
+///
+/// ```ignore
+/// let handler = Chain::new(router)
+///     .with(Csrf::new())
+///     .with(RequireLogin);
+/// ```
+///
+/// Chain is a Handler, so it can be a route handler to wrap one route.
+pub struct Chain<A: ZirconApp> {
+    middlewares: Arc<Vec<Arc<Middleware<A>>>>,
+    handler: Arc<Handler<A>>,
+}
+
+impl<A: ZirconApp> Chain<A> {
+    pub fn new<H: Handler<A>>(handler: H) -> Chain<A> {
+        Chain {
+            middlewares: Arc::new(Vec::new()),
+            handler: Arc::new(handler),
+        }
+    }
+
+    /// Adds a middleware inside the ones already added.
+    /// Use like a builder pattern.
+    pub fn with<M: Middleware<A>>(mut self, middleware: M) -> Chain<A> {
+        Arc::make_mut(&mut self.middlewares).push(Arc::new(middleware));
+        self
+    }
+}
+
+impl<A: ZirconApp> Handler<A> for Chain<A> {
+    fn handle(&self, app: Arc<A>, req: Request) -> HandlerResult {
+        Next::new(self.middlewares.clone(), self.handler.clone()).run(app, req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use futures::Stream;
+    use hyper::Method;
+    use hyper::server::Request as HyperRequest;
+
+    type App = ZirconDefaultApp<()>;
+
+    /// Records the calls to `log`.
+    struct Record {
+        name: &'static str,
+        log: Arc<Mutex<Vec<String>>>,
+        reject: bool,
+    }
+
+    impl Middleware<App> for Record {
+        fn before(&self, _app: &Arc<App>, req: &mut Request) -> Option<HandlerResult> {
+            self.log.lock().unwrap().push(format!("before {}", self.name));
+            if self.reject || req.path() == format!("/reject/{}", self.name) {
+                return Some(ZirconError::render_error_status(StatusCode::Forbidden));
+            }
+            None
+        }
+
+        fn after(&self, _app: &Arc<App>, resp: &mut Response) {
+            self.log.lock().unwrap().push(format!("after {}", self.name));
+            resp.origin.headers_mut().set_raw(format!("x-{}", self.name), "1");
+        }
+
+        fn around(&self, app: Arc<App>, req: Request, next: Next<App>) -> HandlerResult {
+            self.log.lock().unwrap().push(format!("around {}", self.name));
+            next.run(app, req)
+        }
+    }
+
+    fn call(handler: &Chain<App>, path: &str) -> Result<Response, ZirconError> {
+        let app = Arc::new(ZirconDefaultApp::<()>::from_config(ZirconConfig::dev()));
+        let req = Request::from_internal(HyperRequest::new(Method::Get, path.parse().unwrap()));
+        handler.handle(app, req).wait()
+    }
+
+    fn chain(log: &Arc<Mutex<Vec<String>>>) -> Chain<App> {
+        let inner_log = log.clone();
+        let handler = move |_app: Arc<App>, _req: Request| {
+            inner_log.lock().unwrap().push("handler".to_string());
+            Response::text("ok").render()
+        };
+        Chain::new(handler)
+            .with(Record { name: "a", log: log.clone(), reject: false })
+            .with(Record { name: "b", log: log.clone(), reject: false })
+    }
+
+    #[test]
+    fn order() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let resp = call(&chain(&log), "/").ok().unwrap();
+
+        assert_eq!(*log.lock().unwrap(), vec!["before a", "around a", "before b", "around b", "handler",
+                                              "after b", "after a"]);
+        assert!(resp.origin.headers().get_raw("x-a").is_some());
+        assert!(resp.origin.headers().get_raw("x-b").is_some());
+        let body = resp.origin.body().concat2().wait().ok().unwrap();
+        assert_eq!(&body[..], b"ok");
+    }
+
+    #[test]
+    fn short_circuit() {
+        let log = Arc::new(Mutex::new(Vec::new()));
+        match call(&chain(&log), "/reject/b") {
+            Err(ZirconError::Status(StatusCode::Forbidden)) => (),
+            _ => panic!("403 is expected"),
+        }
+        // `after` is not called for errors.
+        assert_eq!(*log.lock().unwrap(), vec!["before a", "around a", "before b"]);
+
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let handler = Chain::new(|_app: Arc<App>, _req: Request| Response::text("ok").render())
+            .with(Record { name: "a", log: log.clone(), reject: false })
+            .with(Record { name: "b", log: log.clone(), reject: true })
+            .with(Record { name: "c", log: log.clone(), reject: false });
+        assert!(call(&handler, "/").is_err());
+        assert_eq!(*log.lock().unwrap(), vec!["before a", "around a", "before b"]);
+    }
+}
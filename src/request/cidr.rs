@@ -0,0 +1,146 @@
+use std::net::{IpAddr, Ipv4Addr};
+use std::str::FromStr;
+
+/// Cidr is an IP address range such as `10.0.0.0/8` or `fd00::/8`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+/// Converts an IPv4-mapped IPv6 address (`::ffff:a.b.c.d`) to IPv4.
+/// Dual-stack listeners see IPv4 clients in this form.
+fn canonical(addr: &IpAddr) -> IpAddr {
+    if let IpAddr::V6(ref v6) = *addr {
+        let s = v6.segments();
+        if s[0] == 0 && s[1] == 0 && s[2] == 0 && s[3] == 0 && s[4] == 0 && s[5] == 0xffff {
+            return IpAddr::V4(Ipv4Addr::new((s[6] >> 8) as u8, s[6] as u8, (s[7] >> 8) as u8, s[7] as u8));
+        }
+    }
+    *addr
+}
+
+fn octets(addr: &IpAddr) -> Vec<u8> {
+    match *addr {
+        IpAddr::V4(ref x) => x.octets().to_vec(),
+        IpAddr::V6(ref x) => x.octets().to_vec(),
+    }
+}
+
+impl Cidr {
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Result<Cidr, String> {
+        let max_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max_len {
+            return Err(format!("prefix length is too long: {}/{}", addr, prefix_len));
+        }
+
+        Ok(Cidr {
+            addr: addr,
+            prefix_len: prefix_len,
+        })
+    }
+
+    /// Returns true if `addr` is in this range.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        let addr = canonical(addr);
+        let (a, b) = (octets(&self.addr), octets(&addr));
+        if a.len() != b.len() {
+            return false;
+        }
+
+        let full_bytes = (self.prefix_len / 8) as usize;
+        if a[..full_bytes] != b[..full_bytes] {
+            return false;
+        }
+
+        let rest_bits = self.prefix_len % 8;
+        if rest_bits == 0 {
+            return true;
+        }
+        let mask = 0xffu8 << (8 - rest_bits);
+        a[full_bytes] & mask == b[full_bytes] & mask
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    /// Parses `addr/prefix_len`. A bare address is a range of that single address.
+    fn from_str(s: &str) -> Result<Cidr, String> {
+        let (addr_str, len_str) = match s.find('/') {
+            Some(pos) => (&s[..pos], Some(&s[(pos + 1)..])),
+            None => (s, None),
+        };
+
+        let addr: IpAddr = match addr_str.parse() {
+            Ok(x) => x,
+            Err(_) => return Err(format!("cannot parse as cidr: {}", s)),
+        };
+        let prefix_len = match len_str {
+            Some(x) => match x.parse() {
+                Ok(x) => x,
+                Err(_) => return Err(format!("cannot parse as cidr: {}", s)),
+            },
+            None => match addr {
+                IpAddr::V4(_) => 32,
+                IpAddr::V6(_) => 128,
+            },
+        };
+
+        Cidr::new(canonical(&addr), prefix_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_cidr() {
+        assert_eq!("10.0.0.0/8".parse::<Cidr>(), Cidr::new(ip("10.0.0.0"), 8));
+        assert_eq!("::1".parse::<Cidr>(), Cidr::new(ip("::1"), 128));
+        assert_eq!("192.168.0.1".parse::<Cidr>(), Cidr::new(ip("192.168.0.1"), 32));
+
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("10.0.0.0/".parse::<Cidr>().is_err());
+        assert!("foo/8".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn contains_v4() {
+        let cidr: Cidr = "10.1.0.0/16".parse().unwrap();
+        assert!(cidr.contains(&ip("10.1.0.0")));
+        assert!(cidr.contains(&ip("10.1.255.3")));
+        assert!(!cidr.contains(&ip("10.2.0.1")));
+        assert!(!cidr.contains(&ip("::1")));
+
+        let cidr: Cidr = "172.16.0.0/12".parse().unwrap();
+        assert!(cidr.contains(&ip("172.31.255.255")));
+        assert!(!cidr.contains(&ip("172.32.0.0")));
+
+        let all: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains(&ip("8.8.8.8")));
+    }
+
+    #[test]
+    fn contains_v6() {
+        let cidr: Cidr = "fd00::/8".parse().unwrap();
+        assert!(cidr.contains(&ip("fd12:3456::1")));
+        assert!(!cidr.contains(&ip("fe80::1")));
+        assert!(!cidr.contains(&ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn contains_v4_mapped() {
+        let cidr: Cidr = "127.0.0.0/8".parse().unwrap();
+        assert!(cidr.contains(&ip("::ffff:127.0.0.1")));
+        assert!(!cidr.contains(&ip("::ffff:10.0.0.1")));
+    }
+}
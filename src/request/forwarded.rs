@@ -0,0 +1,335 @@
+use std::ascii::AsciiExt;
+use std::fmt;
+use std::net::IpAddr;
+use std::str;
+
+use hyper;
+use hyper::header::{Formatter, Header, Raw};
+
+/// Which headers from a trusted proxy are respected. The other ones are ignored,
+/// since a proxy managing only one of them passes the other as the client sent it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ForwardedHeaders {
+    /// `X-Forwarded-For`, `X-Forwarded-Host`, `X-Forwarded-Port` and `X-Forwarded-Proto`.
+    XForwarded,
+    /// RFC 7239 `Forwarded`.
+    Forwarded,
+}
+
+/// Node is the `for` or `by` parameter of a `Forwarded` element.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Node {
+    Addr(IpAddr, Option<u16>),
+    /// The proxy doesn't know the address.
+    Unknown,
+    /// An identifier hiding the address, e.g. `_hidden`. An obfuscated port is dropped.
+    Obfuscated(String),
+}
+
+impl Node {
+    fn parse(s: &str) -> Option<Node> {
+        let (name, port) = if s.starts_with('[') {
+            let end = s.find(']')?;
+            let port = match &s[(end + 1)..] {
+                "" => None,
+                x if x.starts_with(':') => Some(&x[1..]),
+                _ => return None,
+            };
+            (&s[1..end], port)
+        } else {
+            match s.find(':') {
+                Some(pos) => (&s[..pos], Some(&s[(pos + 1)..])),
+                None => (s, None),
+            }
+        };
+
+        let port = match port {
+            None => None,
+            Some(x) if is_obfuscated(x) => None,
+            Some(x) if x.len() <= 5 && x.bytes().all(|b| b.is_ascii_digit()) => Some(x.parse().ok()?),
+            Some(_) => return None,
+        };
+
+        if name.eq_ignore_ascii_case("unknown") {
+            return Some(Node::Unknown);
+        }
+        if is_obfuscated(name) {
+            return Some(Node::Obfuscated(name.to_string()));
+        }
+
+        // An IPv6 address must be in brackets, and an IPv4 address must not be.
+        match name.parse::<IpAddr>().ok()? {
+            IpAddr::V4(_) if s.starts_with('[') => None,
+            IpAddr::V6(_) if !s.starts_with('[') => None,
+            addr => Some(Node::Addr(addr, port)),
+        }
+    }
+
+    pub fn addr(&self) -> Option<IpAddr> {
+        match *self {
+            Node::Addr(addr, _) => Some(addr),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Node {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Node::Addr(IpAddr::V4(addr), None) => write!(f, "{}", addr),
+            Node::Addr(IpAddr::V4(addr), Some(port)) => write!(f, "\"{}:{}\"", addr, port),
+            Node::Addr(IpAddr::V6(addr), None) => write!(f, "\"[{}]\"", addr),
+            Node::Addr(IpAddr::V6(addr), Some(port)) => write!(f, "\"[{}]:{}\"", addr, port),
+            Node::Unknown => write!(f, "unknown"),
+            Node::Obfuscated(ref name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// obfnode = "_" 1*( ALPHA / DIGIT / "." / "_" / "-" )
+fn is_obfuscated(s: &str) -> bool {
+    s.len() > 1 && s.starts_with('_') &&
+        s.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'.' || b == b'_' || b == b'-')
+}
+
+fn is_tchar(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
+/// ForwardedElement is what one proxy reports.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ForwardedElement {
+    pub for_node: Option<Node>,
+    pub by: Option<Node>,
+    pub host: Option<String>,
+    pub proto: Option<String>,
+}
+
+impl fmt::Display for ForwardedElement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut pairs = Vec::new();
+        if let Some(ref x) = self.for_node {
+            pairs.push(format!("for={}", x));
+        }
+        if let Some(ref x) = self.by {
+            pairs.push(format!("by={}", x));
+        }
+        if let Some(ref x) = self.host {
+            if x.chars().all(is_tchar) {
+                pairs.push(format!("host={}", x));
+            } else {
+                pairs.push(format!("host=\"{}\"", x));
+            }
+        }
+        if let Some(ref x) = self.proto {
+            pairs.push(format!("proto={}", x));
+        }
+        write!(f, "{}", pairs.join(";"))
+    }
+}
+
+/// Forwarded is the RFC 7239 `Forwarded` header, one element per proxy from the farthest one.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Forwarded(pub Vec<ForwardedElement>);
+
+/// Parser reads `Forwarded` values, which are pairs separated by `;`, and elements separated by `,`.
+struct Parser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<char> {
+        self.s[self.pos..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if c != ' ' && c != '\t' {
+                break;
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn token(&mut self) -> Option<&'a str> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if !is_tchar(c) {
+                break;
+            }
+            self.pos += c.len_utf8();
+        }
+
+        if start == self.pos { None } else { Some(&self.s[start..self.pos]) }
+    }
+
+    fn quoted_string(&mut self) -> Option<String> {
+        let mut value = String::new();
+        self.pos += 1;
+        loop {
+            let c = self.peek()?;
+            self.pos += c.len_utf8();
+            match c {
+                '"' => return Some(value),
+                '\\' => {
+                    let c = self.peek()?;
+                    self.pos += c.len_utf8();
+                    value.push(c);
+                },
+                c => value.push(c),
+            }
+        }
+    }
+
+    fn value(&mut self) -> Option<String> {
+        match self.peek() {
+            Some('"') => self.quoted_string(),
+            _ => self.token().map(|x| x.to_string()),
+        }
+    }
+
+    fn element(&mut self) -> Option<ForwardedElement> {
+        let mut element = ForwardedElement::default();
+        loop {
+            self.skip_whitespace();
+            if let Some(name) = self.token() {
+                if self.peek() != Some('=') {
+                    return None;
+                }
+                self.pos += 1;
+                let value = self.value()?;
+
+                match name.to_ascii_lowercase().as_str() {
+                    "for" => element.for_node = Some(Node::parse(&value)?),
+                    "by" => element.by = Some(Node::parse(&value)?),
+                    "host" => element.host = Some(value),
+                    "proto" => element.proto = Some(value.to_ascii_lowercase()),
+                    // Extensions are allowed and ignored.
+                    _ => (),
+                }
+                self.skip_whitespace();
+            }
+
+            match self.peek() {
+                Some(';') => self.pos += 1,
+                _ => return Some(element),
+            }
+        }
+    }
+
+    fn elements(&mut self, result: &mut Vec<ForwardedElement>) -> Option<()> {
+        loop {
+            let element = self.element()?;
+            if element != ForwardedElement::default() {
+                result.push(element);
+            }
+
+            match self.peek() {
+                Some(',') => self.pos += 1,
+                None => return Some(()),
+                Some(_) => return None,
+            }
+        }
+    }
+}
+
+impl Forwarded {
+    /// Parses a header value. Several header lines should be joined with `,`.
+    pub fn parse(s: &str) -> Option<Forwarded> {
+        let mut elements = Vec::new();
+        Parser { s: s, pos: 0 }.elements(&mut elements)?;
+        Some(Forwarded(elements))
+    }
+}
+
+impl Header for Forwarded {
+    fn header_name() -> &'static str {
+        "Forwarded"
+    }
+
+    fn parse_header(raw: &Raw) -> hyper::Result<Forwarded> {
+        let mut elements = Vec::new();
+        for line in raw.iter() {
+            let line = str::from_utf8(line).map_err(|_| hyper::Error::Header)?;
+            let mut parser = Parser { s: line, pos: 0 };
+            parser.elements(&mut elements).ok_or(hyper::Error::Header)?;
+        }
+        Ok(Forwarded(elements))
+    }
+
+    fn fmt_header(&self, f: &mut Formatter) -> fmt::Result {
+        f.fmt_line(self)
+    }
+}
+
+impl fmt::Display for Forwarded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let elements: Vec<String> = self.0.iter().map(|x| x.to_string()).collect();
+        write!(f, "{}", elements.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(s: &str, port: Option<u16>) -> Option<Node> {
+        Some(Node::Addr(s.parse().unwrap(), port))
+    }
+
+    #[test]
+    fn parse_simple() {
+        let f = Forwarded::parse("for=192.0.2.60;proto=http;by=203.0.113.43").unwrap();
+        assert_eq!(f.0, vec![ForwardedElement {
+            for_node: addr("192.0.2.60", None),
+            by: addr("203.0.113.43", None),
+            host: None,
+            proto: Some("http".to_string()),
+        }]);
+    }
+
+    #[test]
+    fn parse_multiple_elements() {
+        let f = Forwarded::parse("for=192.0.2.43, for=198.51.100.17;host=example.com").unwrap();
+        assert_eq!(f.0.len(), 2);
+        assert_eq!(f.0[0].for_node, addr("192.0.2.43", None));
+        assert_eq!(f.0[1].for_node, addr("198.51.100.17", None));
+        assert_eq!(f.0[1].host, Some("example.com".to_string()));
+    }
+
+    #[test]
+    fn parse_quoted_ipv6() {
+        let f = Forwarded::parse("For=\"[2001:db8:cafe::17]:4711\"").unwrap();
+        assert_eq!(f.0[0].for_node, addr("2001:db8:cafe::17", Some(4711)));
+
+        let f = Forwarded::parse("for=\"192.0.2.43:8080\";host=\"example.com:8443\"").unwrap();
+        assert_eq!(f.0[0].for_node, addr("192.0.2.43", Some(8080)));
+        assert_eq!(f.0[0].host, Some("example.com:8443".to_string()));
+
+        // IPv6 without brackets is not allowed.
+        assert_eq!(Forwarded::parse("for=\"2001:db8::1\""), None);
+    }
+
+    #[test]
+    fn parse_obfuscated() {
+        let f = Forwarded::parse("for=unknown, for=_hidden, for=\"_SEVKISEK:_port\"").unwrap();
+        assert_eq!(f.0[0].for_node, Some(Node::Unknown));
+        assert_eq!(f.0[1].for_node, Some(Node::Obfuscated("_hidden".to_string())));
+        assert_eq!(f.0[2].for_node, Some(Node::Obfuscated("_SEVKISEK".to_string())));
+    }
+
+    #[test]
+    fn parse_malformed() {
+        assert_eq!(Forwarded::parse("for"), None);
+        assert_eq!(Forwarded::parse("for=\"192.0.2.1"), None);
+        assert_eq!(Forwarded::parse("for=hostname"), None);
+        assert_eq!(Forwarded::parse("for=192.0.2.1 proto=http"), None);
+    }
+
+    #[test]
+    fn format() {
+        let f = Forwarded::parse("for=192.0.2.60;proto=http, for=\"[2001:db8::1]:80\";host=\"a b\"").unwrap();
+        assert_eq!(f.to_string(), "for=192.0.2.60;proto=http, for=\"[2001:db8::1]:80\";host=\"a b\"");
+    }
+}
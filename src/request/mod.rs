@@ -1,20 +1,34 @@
+mod cidr;
+mod forwarded;
 mod query;
 
+use std::cmp;
+use std::io;
+use std::mem;
 use std::net::IpAddr;
 use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
 // TODO(mayah): Might hit https://github.com/alexcrichton/futures-rs/issues/362 ?
 // use futures::Future;
 use futures;
 use futures::Stream;
+use futures::stream;
+use connection::ReadTimeout;
 use handlers::router::RouteResult;
 use hyper::server::Request as HyperRequest;
-use hyper::{self, Method, Uri, HttpVersion, Headers, Body};
+use hyper::header::Host;
+use hyper::{self, Method, Uri, HttpVersion, Headers, Chunk};
 use serde_json::value::Value as Json;
+use tokio_core::reactor::{Handle, Timeout};
 use typemap::TypeMap;
 
 use prelude::*;
 
+pub use self::cidr::Cidr;
+pub use self::forwarded::{Forwarded, ForwardedElement, ForwardedHeaders, Node};
 pub use self::query::Query;
 
 header! { (XForwardedHost, "X-Forwarded-Host") => [String] }
@@ -22,20 +36,40 @@ header! { (XForwardedPort, "X-Forwarded-Port") => [u16] }
 header! { (XForwardedProto, "X-Forwarded-Proto") => [String] }
 header! { (XForwardedFor, "X-Forwarded-For") => (IpAddr)+ }
 
+/// Splits `host:port` into host and port. IPv6 literals are kept in brackets as in the Host header.
+fn split_port(host: &str) -> (&str, Option<u16>) {
+    let end = if host.starts_with('[') {
+        host.find(']').map(|pos| pos + 1)
+    } else {
+        host.find(':')
+    };
+
+    match end {
+        Some(pos) => (&host[..pos], host[pos..].trim_left_matches(':').parse().ok()),
+        None => (host, None),
+    }
+}
+
 pub struct RequestHeader {
     // From HyperRequest.
     method: Method,
     uri: Uri,
-    _version: HttpVersion,
+    version: HttpVersion,
     headers: Headers,
-    _remote_addr: Option<SocketAddr>,
+    peer_addr: Option<SocketAddr>,
 
     /// Routing result.
     pub params: Option<RouteResult>,
     /// Path (if modified from the original).
     pub modified_path: Option<String>,
+    /// True if the request came over TLS.
+    pub secure: bool,
     /// Respect XForwarded*.
     pub respect_xforwarded: bool,
+    /// Peers whose Forwarded and X-Forwarded-* are respected.
+    pub trusted_proxies: Arc<Vec<Cidr>>,
+    /// Which of Forwarded and X-Forwarded-* is respected.
+    pub forwarded_headers: ForwardedHeaders,
     /// Extension
     pub extensions: TypeMap,
 }
@@ -45,19 +79,175 @@ impl RequestHeader {
         &self.method
     }
 
+    pub fn version(&self) -> HttpVersion {
+        self.version
+    }
+
     pub fn headers(&self) -> &hyper::Headers {
         &self.headers
     }
 
+    pub fn headers_mut(&mut self) -> &mut hyper::Headers {
+        &mut self.headers
+    }
+
+    /// Returns the address of the peer, which is the proxy when behind a reverse proxy.
+    /// None is returned when the connection has no socket address, e.g. on a Unix domain socket.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    pub fn set_peer_addr(&mut self, addr: Option<SocketAddr>) {
+        self.peer_addr = addr;
+    }
+
+    fn is_trusted_proxy(&self, addr: &IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|cidr| cidr.contains(addr))
+    }
+
+    /// Returns true if Forwarded and X-Forwarded-* of this request should be respected.
+    /// A peer without a socket address (a Unix domain socket) can be connected only locally,
+    /// so it is trusted.
+    pub fn is_from_trusted_proxy(&self) -> bool {
+        if !self.respect_xforwarded {
+            return false;
+        }
+
+        match self.peer_addr {
+            Some(addr) => self.is_trusted_proxy(&addr.ip()),
+            None => true,
+        }
+    }
+
+    /// Returns what the trusted proxy says in the headers chosen by `forwarded_headers`.
+    fn proxied<T, F, X>(&self, forwarded: F, xforwarded: X) -> Option<T>
+    where F: FnOnce() -> Option<T>, X: FnOnce() -> Option<T> {
+        if !self.is_from_trusted_proxy() {
+            return None;
+        }
+
+        match self.forwarded_headers {
+            ForwardedHeaders::XForwarded => xforwarded(),
+            ForwardedHeaders::Forwarded => forwarded(),
+        }
+    }
+
+    /// Returns the Forwarded element added by the proxy that the client connected to.
+    ///
+    /// Elements are read from the nearest hop, and the first one whose `for` is not a trusted proxy
+    /// is taken. Elements before it are ignored, since the client can write anything there.
+    fn forwarded_element(&self) -> Option<&ForwardedElement> {
+        let forwarded = self.headers.get::<Forwarded>()?;
+        let is_proxy = |e: &&ForwardedElement| {
+            match e.for_node {
+                Some(Node::Addr(ref addr, _)) => self.is_trusted_proxy(addr),
+                _ => false,
+            }
+        };
+
+        // When every hop is a trusted proxy, the farthest one is the client.
+        forwarded.0.iter().rev().find(|e| !is_proxy(e)).or_else(|| forwarded.0.first())
+    }
+
+    /// Walks X-Forwarded-For in the same way as `forwarded_element`.
+    fn xforwarded_for(&self) -> Option<IpAddr> {
+        let xff = self.headers.get::<XForwardedFor>()?;
+        xff.iter().rev().find(|addr| !self.is_trusted_proxy(addr)).or_else(|| xff.first()).cloned()
+    }
+
+    /// Returns the address of the client.
+    ///
+    /// When the peer is a trusted proxy, the address is taken from X-Forwarded-For or Forwarded,
+    /// skipping the trusted proxies on the way. If the proxy hides the client address,
+    /// e.g. `for=unknown`, the address of the peer is returned; X-Forwarded-For is not consulted then.
+    pub fn remote_addr(&self) -> Option<IpAddr> {
+        let forwarded = move || self.forwarded_element().and_then(|e| e.for_node.as_ref()).and_then(|n| n.addr());
+        let xforwarded = move || self.xforwarded_for();
+
+        match self.proxied(forwarded, xforwarded) {
+            Some(addr) => Some(addr),
+            None => self.peer_addr.map(|addr| addr.ip()),
+        }
+    }
+
+    /// Returns the host the client requested, with port if explicit.
+    fn host_port(&self) -> Option<&str> {
+        let forwarded = move || self.forwarded_element().and_then(|e| e.host.as_ref()).map(|x| x.as_str());
+        let xforwarded = move || self.xforwarded_host();
+        self.proxied(forwarded, xforwarded)
+    }
+
+    /// Returns the X-Forwarded-Host entry added by the proxy that the client connected to.
+    ///
+    /// Each proxy appends its own entry, and the client can write anything before them.
+    /// The proxies after that one are the trusted hops at the end of X-Forwarded-For,
+    /// so as many entries are skipped from the end.
+    fn xforwarded_host(&self) -> Option<&str> {
+        let x = self.headers.get::<XForwardedHost>()?;
+        let hosts: Vec<&str> = x.0.split(',').map(|h| h.trim()).collect();
+        let hops = match self.headers.get::<XForwardedFor>() {
+            // When every hop is a trusted proxy, the farthest one is the client.
+            Some(xff) => cmp::min(xff.iter().rev().take_while(|addr| self.is_trusted_proxy(addr)).count(),
+                                  xff.len() - 1),
+            None => 0,
+        };
+        let host = if hops < hosts.len() { hosts[hosts.len() - 1 - hops] } else { hosts[0] };
+        if host.is_empty() { None } else { Some(host) }
+    }
+
+    /// Returns the host name the client requested, without port.
+    pub fn host(&self) -> Option<&str> {
+        if let Some(host) = self.host_port() {
+            return Some(split_port(host).0);
+        }
+
+        match self.headers.get::<Host>() {
+            Some(x) => Some(x.hostname()),
+            None => self.uri.host(),
+        }
+    }
+
+    /// Returns the port the client requested.
+    /// What the trusted proxy says (the port of the forwarded host, then X-Forwarded-Port) wins over
+    /// the Host header. When not explicit, the default port of the scheme is returned.
+    pub fn port(&self) -> Option<u16> {
+        let port = self.host_port().and_then(|host| split_port(host).1).or_else(|| {
+            self.proxied(|| None, || self.headers.get::<XForwardedPort>().map(|x| x.0))
+        });
+        if port.is_some() {
+            return port;
+        }
+
+        // The Host header is the one the proxy requested if the host is forwarded.
+        if self.host_port().is_none() {
+            let port = match self.headers.get::<Host>() {
+                Some(x) => x.port(),
+                None => self.uri.port(),
+            };
+            if port.is_some() {
+                return port;
+            }
+        }
+
+        match self.scheme() {
+            Some("https") => Some(443),
+            Some("http") => Some(80),
+            _ => None,
+        }
+    }
+
     /// Returns scheme.
     pub fn scheme(&self) -> Option<&str> {
-        if self.respect_xforwarded {
-            match self.headers.get::<XForwardedProto>() {
-                Some(x) => Some(&x.0),
-                None => self.uri.scheme(),
-            }
-        } else {
-            self.uri.scheme()
+        let forwarded = move || self.forwarded_element().and_then(|e| e.proto.as_ref()).map(|x| x.as_str());
+        let xforwarded = move || self.headers.get::<XForwardedProto>().map(|x| x.0.as_str());
+        if let Some(x) = self.proxied(forwarded, xforwarded) {
+            return Some(x);
+        }
+
+        match self.uri.scheme() {
+            Some(x) => Some(x),
+            None if self.secure => Some("https"),
+            None => Some("http"),
         }
     }
 
@@ -76,15 +266,41 @@ impl RequestHeader {
         self.modified_path = path
     }
 
-    /// Returns routing parameter. Not from query string.
+    /// Returns routing parameter, percent-decoded. Not from query string.
     pub fn param(&self, key: &str) -> Option<&str> {
-        self.params.as_ref().unwrap().param(key)
+        self.params.as_ref().and_then(|p| p.param(key))
+    }
+
+    /// Returns routing parameter as it is in the path, with `%xx` escapes.
+    pub fn raw_param(&self, key: &str) -> Option<&str> {
+        self.params.as_ref().and_then(|p| p.raw_param(key))
+    }
+
+    /// Returns routing parameter parsed as `T`, e.g. `req.param_as::<u64>("id")`.
+    /// If it is missing or doesn't parse, 400 Bad Request is returned as error.
+    pub fn param_as<T: FromStr>(&self, key: &str) -> Result<T, ZirconError> {
+        let value = match self.params.as_ref().and_then(|p| p.param(key)) {
+            Some(x) => x,
+            None if self.raw_param(key).is_some() => {
+                return Err(ZirconError::message(StatusCode::BadRequest, format!("param {} is not valid UTF-8", key)));
+            },
+            None => return Err(ZirconError::message(StatusCode::BadRequest, format!("param {} is missing", key))),
+        };
+
+        value.parse().map_err(|_| {
+            ZirconError::message(StatusCode::BadRequest, format!("param {} is invalid: {}", key, value))
+        })
     }
 
     pub fn set_params(&mut self, result: Option<RouteResult>) {
         self.params = result;
     }
 
+    /// Returns the requested URI. Its path is not modified by mount points.
+    pub fn uri(&self) -> &Uri {
+        &self.uri
+    }
+
     /// Parses query string.
     pub fn parse_query(&self) -> Query {
         if let Some(q) = self.uri.query() {
@@ -96,10 +312,18 @@ impl RequestHeader {
 }
 
 pub struct RequestBody {
-    body: Body,
+    body: Box<Stream<Item=Chunk, Error=hyper::Error>>,
 }
 
 impl RequestBody {
+    /// Makes reading the body fail when the client sends nothing for `timeout`.
+    pub fn set_read_timeout(&mut self, timeout: Duration, handle: &Handle) -> io::Result<()> {
+        let timer = Timeout::new(timeout, handle)?;
+        let body = mem::replace(&mut self.body, Box::new(stream::empty()));
+        self.body = Box::new(ReadTimeout::new(body, timeout, timer));
+        Ok(())
+    }
+
     /// Parses form body. multipart is not supported yet.
     ///
     /// When using this function, your source must to have `use futures::Future`.
@@ -145,22 +369,25 @@ pub struct Request {
 
 impl Request {
     pub fn from_internal(origin: HyperRequest) -> Request {
-        let remote_addr = origin.remote_addr();
+        let peer_addr = origin.remote_addr();
         let (method, uri, version, headers, body) = origin.deconstruct();
         Request {
             header: RequestHeader {
                 method: method,
                 uri: uri,
-                _version: version,
+                version: version,
                 headers: headers,
-                _remote_addr: remote_addr,
+                peer_addr: peer_addr,
                 params: None,
                 modified_path: None,
-                respect_xforwarded: false,  // TODO(mayah): Copy this from ZirconConfig.
+                secure: false,
+                respect_xforwarded: false,
+                trusted_proxies: Arc::new(Vec::new()),
+                forwarded_headers: ForwardedHeaders::XForwarded,
                 extensions: TypeMap::new(),
             },
             body: RequestBody {
-                body: body,
+                body: Box::new(body),
             },
         }
     }
@@ -173,15 +400,48 @@ impl Request {
         self.header.method()
     }
 
+    pub fn version(&self) -> HttpVersion {
+        self.header.version()
+    }
+
     pub fn headers(&self) -> &hyper::Headers {
         self.header.headers()
     }
 
+    pub fn headers_mut(&mut self) -> &mut hyper::Headers {
+        self.header.headers_mut()
+    }
+
+    /// Returns the address of the peer, which is the proxy when behind a reverse proxy.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.header.peer_addr()
+    }
+
+    /// Returns the address of the client. See `RequestHeader::remote_addr()`.
+    pub fn remote_addr(&self) -> Option<IpAddr> {
+        self.header.remote_addr()
+    }
+
+    /// Returns the host name the client requested, without port.
+    pub fn host(&self) -> Option<&str> {
+        self.header.host()
+    }
+
+    /// Returns the port the client requested.
+    pub fn port(&self) -> Option<u16> {
+        self.header.port()
+    }
+
     /// Returns scheme.
     pub fn scheme(&self) -> Option<&str> {
         self.header.scheme()
     }
 
+    /// Returns the requested URI. Its path is not modified by mount points.
+    pub fn uri(&self) -> &Uri {
+        self.header.uri()
+    }
+
     pub fn path(&self) -> &str {
         self.header.path()
     }
@@ -194,11 +454,21 @@ impl Request {
         self.header.set_modified_path(path)
     }
 
-    /// Returns routing parameter. Not from query string.
+    /// Returns routing parameter, percent-decoded. Not from query string.
     pub fn param(&self, key: &str) -> Option<&str> {
         self.header.param(key)
     }
 
+    /// Returns routing parameter as it is in the path. See `RequestHeader::raw_param`.
+    pub fn raw_param(&self, key: &str) -> Option<&str> {
+        self.header.raw_param(key)
+    }
+
+    /// Returns routing parameter parsed as `T`. See `RequestHeader::param_as`.
+    pub fn param_as<T: FromStr>(&self, key: &str) -> Result<T, ZirconError> {
+        self.header.param_as(key)
+    }
+
     /// Set routing parameter.
     pub fn set_params(&mut self, result: Option<RouteResult>) {
         self.header.set_params(result)
@@ -217,3 +487,165 @@ impl Request {
         &mut self.header.extensions
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(peer: &str, headers: &[(&'static str, &str)]) -> Request {
+        let mut origin = HyperRequest::new(Method::Get, "/".parse().unwrap());
+        for &(name, value) in headers {
+            origin.headers_mut().set_raw(name, value.to_string());
+        }
+
+        let mut req = Request::from_internal(origin);
+        req.header.set_peer_addr(Some(peer.parse().unwrap()));
+        req.header.respect_xforwarded = true;
+        req.header.trusted_proxies = Arc::new(vec!["10.0.0.0/8".parse().unwrap()]);
+        req
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn remote_addr_from_trusted_proxy() {
+        let req = request("10.0.0.1:1234", &[("X-Forwarded-For", "192.0.2.1, 10.0.0.2")]);
+        assert_eq!(req.remote_addr(), ip("192.0.2.1"));
+        assert_eq!(req.peer_addr(), Some("10.0.0.1:1234".parse().unwrap()));
+    }
+
+    #[test]
+    fn remote_addr_ignores_spoofed_hops() {
+        // The client sent "X-Forwarded-For: 198.51.100.1", and the proxy appended the client address.
+        let req = request("10.0.0.1:1234", &[("X-Forwarded-For", "198.51.100.1, 192.0.2.1")]);
+        assert_eq!(req.remote_addr(), ip("192.0.2.1"));
+    }
+
+    #[test]
+    fn remote_addr_from_untrusted_peer() {
+        let req = request("192.0.2.1:1234", &[("X-Forwarded-For", "198.51.100.1")]);
+        assert_eq!(req.remote_addr(), ip("192.0.2.1"));
+
+        let mut req = request("10.0.0.1:1234", &[("X-Forwarded-For", "198.51.100.1")]);
+        req.header.respect_xforwarded = false;
+        assert_eq!(req.remote_addr(), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn host_and_port() {
+        // The client sent "X-Forwarded-Host: evil.com", and the proxy appended the requested host.
+        let req = request("10.0.0.1:1234", &[("Host", "internal:8080"),
+                                             ("X-Forwarded-Host", "evil.com, example.com:8443"),
+                                             ("X-Forwarded-Port", "8443"),
+                                             ("X-Forwarded-Proto", "https")]);
+        assert_eq!(req.host(), Some("example.com"));
+        assert_eq!(req.port(), Some(8443));
+        assert_eq!(req.scheme(), Some("https"));
+
+        let req = request("192.0.2.1:1234", &[("Host", "internal:8080"),
+                                              ("X-Forwarded-Host", "example.com"),
+                                              ("X-Forwarded-Proto", "https")]);
+        assert_eq!(req.host(), Some("internal"));
+        assert_eq!(req.port(), Some(8080));
+        assert_eq!(req.scheme(), Some("http"));
+
+        let req = request("192.0.2.1:1234", &[("Host", "example.com")]);
+        assert_eq!(req.port(), Some(80));
+    }
+
+    #[test]
+    fn host_through_proxies() {
+        // The client connected to 10.0.0.2, which forwarded to 10.0.0.1.
+        let req = request("10.0.0.1:1234", &[("X-Forwarded-For", "192.0.2.1, 10.0.0.2"),
+                                             ("X-Forwarded-Host", "evil.com, example.com, internal")]);
+        assert_eq!(req.host(), Some("example.com"));
+
+        let req = request("10.0.0.1:1234", &[("X-Forwarded-For", "10.0.0.3"),
+                                             ("X-Forwarded-Host", "example.com")]);
+        assert_eq!(req.host(), Some("example.com"));
+    }
+
+    #[test]
+    fn port_without_forwarded_host() {
+        let req = request("10.0.0.1:1234", &[("Host", "internal:8080"),
+                                             ("X-Forwarded-Port", "8443"),
+                                             ("X-Forwarded-Proto", "https")]);
+        assert_eq!(req.host(), Some("internal"));
+        assert_eq!(req.port(), Some(8443));
+
+        // Forwarded host without port and no X-Forwarded-Port: the default port of the forwarded scheme.
+        let req = request("10.0.0.1:1234", &[("Host", "internal:8080"),
+                                             ("X-Forwarded-Host", "example.com"),
+                                             ("X-Forwarded-Proto", "https")]);
+        assert_eq!(req.port(), Some(443));
+    }
+
+    fn forwarded_request(peer: &str, headers: &[(&'static str, &str)]) -> Request {
+        let mut req = request(peer, headers);
+        req.header.forwarded_headers = ForwardedHeaders::Forwarded;
+        req
+    }
+
+    #[test]
+    fn forwarded() {
+        let headers = [("Forwarded", "for=198.51.100.1, for=192.0.2.1;host=example.com;proto=https"),
+                       ("X-Forwarded-For", "192.0.2.2"),
+                       ("X-Forwarded-Host", "example.org")];
+        let req = forwarded_request("10.0.0.1:1234", &headers);
+        assert_eq!(req.remote_addr(), ip("192.0.2.1"));
+        assert_eq!(req.host(), Some("example.com"));
+        assert_eq!(req.port(), Some(443));
+        assert_eq!(req.scheme(), Some("https"));
+
+        // Forwarded is ignored by default, even when X-Forwarded-Proto is missing.
+        let req = request("10.0.0.1:1234", &headers);
+        assert_eq!(req.remote_addr(), ip("192.0.2.2"));
+        assert_eq!(req.host(), Some("example.org"));
+        assert_eq!(req.scheme(), Some("http"));
+    }
+
+    #[test]
+    fn forwarded_hidden_client() {
+        let req = forwarded_request("10.0.0.1:1234", &[("Forwarded", "for=_hidden;host=\"example.com:8443\"")]);
+        assert_eq!(req.remote_addr(), ip("10.0.0.1"));
+        assert_eq!(req.host(), Some("example.com"));
+        assert_eq!(req.port(), Some(8443));
+    }
+
+    #[test]
+    fn forwarded_ignores_xforwarded() {
+        // X-Forwarded-For is passed through from the client by a proxy managing only Forwarded.
+        let req = forwarded_request("10.0.0.1:1234", &[("Forwarded", "for=_hidden"),
+                                                       ("X-Forwarded-For", "198.51.100.1"),
+                                                       ("X-Forwarded-Host", "evil.com"),
+                                                       ("X-Forwarded-Proto", "https")]);
+        assert_eq!(req.remote_addr(), ip("10.0.0.1"));
+        assert_eq!(req.host(), None);
+        assert_eq!(req.scheme(), Some("http"));
+    }
+
+    #[test]
+    fn forwarded_from_untrusted_peer() {
+        let req = forwarded_request("192.0.2.1:1234", &[("Forwarded", "for=198.51.100.1;proto=https")]);
+        assert_eq!(req.remote_addr(), ip("192.0.2.1"));
+        assert_eq!(req.scheme(), Some("http"));
+    }
+
+    #[test]
+    fn params_outside_router() {
+        let req = request("10.0.0.1:1234", &[]);
+        assert_eq!(req.param("id"), None);
+        assert_eq!(req.raw_param("id"), None);
+        assert!(req.param_as::<u64>("id").is_err());
+    }
+
+    #[test]
+    fn split_port_from_host() {
+        assert_eq!(split_port("example.com:80"), ("example.com", Some(80)));
+        assert_eq!(split_port("example.com"), ("example.com", None));
+        assert_eq!(split_port("[::1]:80"), ("[::1]", Some(80)));
+        assert_eq!(split_port("[::1]"), ("[::1]", None));
+    }
+}
@@ -0,0 +1,59 @@
+use std::sync::{Arc, Mutex};
+
+use futures::Future;
+use futures::sync::oneshot;
+
+struct State {
+    triggered: bool,
+    waiters: Vec<oneshot::Sender<()>>,
+}
+
+/// Shutdown is a handle to stop a running server.
+///
+/// When `shutdown()` is called, every accept loop stops accepting new connections,
+/// the requests already in flight are given `ZirconConfig::shutdown_timeout()` to finish,
+/// and then `Zircon::http` returns.
+#[derive(Clone)]
+pub struct Shutdown {
+    state: Arc<Mutex<State>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown {
+            state: Arc::new(Mutex::new(State {
+                triggered: false,
+                waiters: Vec::new(),
+            })),
+        }
+    }
+
+    /// Requests the server to shut down. Calling this more than once is harmless.
+    pub fn shutdown(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.triggered = true;
+        for tx in state.waiters.drain(..) {
+            let _ = tx.send(());
+        }
+    }
+
+    /// Returns true if shutdown has been requested.
+    pub fn is_shutdown(&self) -> bool {
+        self.state.lock().unwrap().triggered
+    }
+
+    /// Returns a future that resolves when shutdown is requested.
+    /// If shutdown has already been requested, the future resolves immediately.
+    pub fn wait(&self) -> Box<Future<Item=(), Error=()> + Send> {
+        let (tx, rx) = oneshot::channel();
+
+        let mut state = self.state.lock().unwrap();
+        if state.triggered {
+            let _ = tx.send(());
+        } else {
+            state.waiters.push(tx);
+        }
+
+        Box::new(rx.map_err(|_canceled| ()))
+    }
+}
@@ -0,0 +1,42 @@
+use std::env;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::process;
+
+use listener::Listener;
+use upgrade::set_cloexec;
+
+/// The first file descriptor passed by systemd.
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+/// Takes the listening sockets passed by systemd socket activation (`LISTEN_PID` and `LISTEN_FDS`).
+/// None is returned if no socket is passed to this process.
+///
+/// The variables are removed from the environment, so that the sockets are taken only once
+/// and child processes don't try to take them. The sockets are set close-on-exec for the same reason;
+/// `UpgradeHandle` passes them to the new process explicitly.
+pub fn listen_fds() -> io::Result<Option<Vec<Listener>>> {
+    match env::var("LISTEN_PID") {
+        Ok(ref pid) if pid.parse::<u32>().ok() == Some(process::id()) => (),
+        _ => return Ok(None),
+    }
+
+    let num_fds = env::var("LISTEN_FDS").ok().and_then(|n| n.parse::<RawFd>().ok());
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    let num_fds = match num_fds {
+        Some(n) if n > 0 => n,
+        Some(_) => return Ok(None),
+        None => return Err(io::Error::new(io::ErrorKind::InvalidInput, "LISTEN_FDS is not a number")),
+    };
+
+    let mut listeners = Vec::new();
+    for fd in SD_LISTEN_FDS_START..(SD_LISTEN_FDS_START + num_fds) {
+        set_cloexec(fd)?;
+        listeners.push(unsafe { Listener::from_raw_fd(fd)? });
+    }
+
+    Ok(Some(listeners))
+}
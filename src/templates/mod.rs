@@ -1,14 +1,17 @@
 mod source;
 mod sources;
+mod url_for;
 
 use std::sync::{RwLock, RwLockWriteGuard};
 
 use handlebars::Handlebars;
 
 use config::Mode;
+use handlers::router::UrlMap;
 
 pub use self::source::{Source, SourceError};
 pub use self::sources::directory::DirectorySource;
+pub use self::url_for::UrlForHelper;
 
 /// The handlebars template engine
 pub struct HandlebarsEngine {
@@ -55,4 +58,9 @@ impl HandlebarsEngine {
     pub fn handlebars_mut(&self) -> RwLockWriteGuard<Box<Handlebars>> {
         self.registry.write().unwrap()
     }
+
+    /// register `url_for` helper that makes URLs of named routes, e.g. `{{url_for "issue" id=issue.id}}`
+    pub fn register_url_for(&self, url_map: UrlMap) {
+        self.handlebars_mut().register_helper("url_for", Box::new(UrlForHelper::new(url_map)));
+    }
 }
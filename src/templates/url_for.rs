@@ -0,0 +1,44 @@
+use std::io::Write;
+
+use handlebars::{Handlebars, Helper, HelperDef, RenderContext, RenderError};
+use serde_json::value::Value as Json;
+
+use handlers::router::UrlMap;
+
+/// UrlForHelper is the `url_for` helper, e.g. `{{url_for "issue" user=user.name id=issue.id}}`.
+pub struct UrlForHelper {
+    url_map: UrlMap,
+}
+
+impl UrlForHelper {
+    pub fn new(url_map: UrlMap) -> UrlForHelper {
+        UrlForHelper {
+            url_map: url_map,
+        }
+    }
+}
+
+impl HelperDef for UrlForHelper {
+    fn call(&self, h: &Helper, _: &Handlebars, rc: &mut RenderContext) -> Result<(), RenderError> {
+        let name = match h.param(0).map(|x| x.value()) {
+            Some(&Json::String(ref x)) => x,
+            _ => return Err(RenderError::new("url_for: route name is required")),
+        };
+
+        let mut values = Vec::new();
+        for (key, value) in h.hash() {
+            let value = match *value.value() {
+                Json::String(ref x) => x.clone(),
+                Json::Number(ref x) => x.to_string(),
+                Json::Bool(x) => x.to_string(),
+                _ => return Err(RenderError::new(format!("url_for: param {} is not a string", key))),
+            };
+            values.push((key.as_str(), value));
+        }
+        let params: Vec<(&str, &str)> = values.iter().map(|&(k, ref v)| (k, v.as_str())).collect();
+
+        let url = self.url_map.url_for(name, &params).map_err(|err| RenderError::new(format!("url_for: {}", err)))?;
+        rc.writer.write_all(url.as_bytes())?;
+        Ok(())
+    }
+}
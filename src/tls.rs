@@ -0,0 +1,173 @@
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
+
+use rustls::internal::pemfile;
+use rustls::sign::{self, CertifiedKey};
+use rustls::{Certificate, NoClientAuth, PrivateKey, ResolvesServerCert, ServerConfig, SignatureScheme};
+use webpki::DNSNameRef;
+
+fn invalid_data<P: AsRef<Path>>(path: P, what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.as_ref().display(), what))
+}
+
+fn load_certs(path: &Path) -> io::Result<Vec<Certificate>> {
+    let mut reader = BufReader::new(File::open(path)?);
+    match pemfile::certs(&mut reader) {
+        Ok(ref certs) if certs.is_empty() => Err(invalid_data(path, "no certificate found")),
+        Ok(certs) => Ok(certs),
+        Err(()) => Err(invalid_data(path, "malformed certificate")),
+    }
+}
+
+/// Loads a private key. Both PKCS#8 (`BEGIN PRIVATE KEY`) and PKCS#1 (`BEGIN RSA PRIVATE KEY`) are accepted.
+fn load_private_key(path: &Path) -> io::Result<PrivateKey> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let keys = pemfile::pkcs8_private_keys(&mut reader).map_err(|_| invalid_data(path, "malformed private key"))?;
+    if let Some(key) = keys.into_iter().next() {
+        return Ok(key);
+    }
+
+    let mut reader = BufReader::new(File::open(path)?);
+    let keys = pemfile::rsa_private_keys(&mut reader).map_err(|_| invalid_data(path, "malformed private key"))?;
+    match keys.into_iter().next() {
+        Some(key) => Ok(key),
+        None => Err(invalid_data(path, "no private key found")),
+    }
+}
+
+/// A certificate chain and its private key, both PEM files.
+#[derive(Clone)]
+struct CertPaths {
+    cert: PathBuf,
+    key: PathBuf,
+}
+
+impl CertPaths {
+    fn load(&self) -> io::Result<CertifiedKey> {
+        let certs = load_certs(&self.cert)?;
+        let key = load_private_key(&self.key)?;
+        let key = sign::any_supported_type(&key).map_err(|_| invalid_data(&self.key, "unsupported private key"))?;
+        Ok(CertifiedKey::new(certs, Arc::new(key)))
+    }
+}
+
+struct Certs {
+    default: CertifiedKey,
+    by_name: HashMap<String, CertifiedKey>,
+}
+
+struct Paths {
+    default: CertPaths,
+    by_name: Vec<(String, CertPaths)>,
+}
+
+impl Paths {
+    fn load(&self) -> io::Result<Certs> {
+        let mut by_name = HashMap::new();
+        for &(ref name, ref paths) in &self.by_name {
+            by_name.insert(name.clone(), paths.load()?);
+        }
+
+        Ok(Certs {
+            default: self.default.load()?,
+            by_name: by_name,
+        })
+    }
+}
+
+/// CertStore keeps the loaded certificates and chooses one by SNI on every handshake.
+struct CertStore {
+    paths: Mutex<Paths>,
+    certs: RwLock<Certs>,
+}
+
+impl ResolvesServerCert for CertStore {
+    fn resolve(&self, server_name: Option<DNSNameRef>, _sigschemes: &[SignatureScheme]) -> Option<CertifiedKey> {
+        let certs = self.certs.read().unwrap();
+
+        if let Some(server_name) = server_name {
+            let name: &str = server_name.into();
+            let name = name.to_ascii_lowercase();
+            if let Some(ck) = certs.by_name.get(&name) {
+                return Some(ck.clone());
+            }
+            if let Some(pos) = name.find('.') {
+                if let Some(ck) = certs.by_name.get(&format!("*{}", &name[pos..])) {
+                    return Some(ck.clone());
+                }
+            }
+        }
+
+        Some(certs.default.clone())
+    }
+}
+
+/// TlsConfig is the certificates for `Zircon::https`.
+///
+/// Clones share the same certificates, so keep a clone to call `reload()` after
+/// the certificate files are renewed.
+#[derive(Clone)]
+pub struct TlsConfig {
+    store: Arc<CertStore>,
+}
+
+impl TlsConfig {
+    /// Creates a TLS configuration from PEM files.
+    /// This certificate is used when the client sends no SNI or no other certificate matches.
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(cert_path: P, key_path: Q) -> io::Result<TlsConfig> {
+        let paths = Paths {
+            default: CertPaths {
+                cert: cert_path.as_ref().to_path_buf(),
+                key: key_path.as_ref().to_path_buf(),
+            },
+            by_name: Vec::new(),
+        };
+        let certs = paths.load()?;
+
+        Ok(TlsConfig {
+            store: Arc::new(CertStore {
+                paths: Mutex::new(paths),
+                certs: RwLock::new(certs),
+            }),
+        })
+    }
+
+    /// Adds a certificate chosen when the client asks for `server_name` by SNI.
+    /// `*.example.com` matches any single label under `example.com`.
+    pub fn with_sni_cert<P: AsRef<Path>, Q: AsRef<Path>>(self, server_name: &str, cert_path: P, key_path: Q)
+                                                          -> io::Result<TlsConfig> {
+        let name = server_name.to_ascii_lowercase();
+        let paths = CertPaths {
+            cert: cert_path.as_ref().to_path_buf(),
+            key: key_path.as_ref().to_path_buf(),
+        };
+        let ck = paths.load()?;
+
+        self.store.paths.lock().unwrap().by_name.push((name.clone(), paths));
+        self.store.certs.write().unwrap().by_name.insert(name, ck);
+        Ok(self)
+    }
+
+    /// Reloads every certificate and private key from disk.
+    /// If any of them fails to load, the current certificates stay in use and the error is returned.
+    /// Connections already established keep their certificate.
+    pub fn reload(&self) -> io::Result<()> {
+        let certs = self.store.paths.lock().unwrap().load()?;
+        *self.store.certs.write().unwrap() = certs;
+        info!("reloaded TLS certificates");
+        Ok(())
+    }
+
+    /// Makes a rustls server configuration that resolves certificates from this TlsConfig.
+    pub fn server_config(&self) -> Arc<ServerConfig> {
+        let mut config = ServerConfig::new(NoClientAuth::new());
+        config.cert_resolver = self.store.clone();
+        // hyper speaks only HTTP/1.1.
+        config.set_protocols(&["http/1.1".to_string()]);
+        Arc::new(config)
+    }
+}
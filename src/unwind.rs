@@ -0,0 +1,96 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Once, ONCE_INIT};
+
+use futures::{Future, IntoFuture};
+use prelude::*;
+
+thread_local!(static PANIC_LOCATION: RefCell<Option<String>> = RefCell::new(None));
+
+static INSTALL_HOOK: Once = ONCE_INIT;
+
+/// Installs a panic hook remembering where the last panic of each thread happened,
+/// since the panic payload doesn't have it. The previous hook is still called.
+fn install_hook() {
+    INSTALL_HOOK.call_once(|| {
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let location = info.location().map(|x| format!("{}:{}:{}", x.file(), x.line(), x.column()));
+            PANIC_LOCATION.with(|x| *x.borrow_mut() = location);
+            previous(info);
+        }));
+    });
+}
+
+fn panic_error(payload: Box<Any + Send>) -> ZirconError {
+    let message = if let Some(x) = payload.downcast_ref::<&str>() {
+        x.to_string()
+    } else if let Some(x) = payload.downcast_ref::<String>() {
+        x.clone()
+    } else {
+        "Box<Any>".to_string()
+    };
+    let location = PANIC_LOCATION.with(|x| x.borrow_mut().take()).unwrap_or_else(|| "unknown location".to_string());
+
+    error!("handler panicked at {}: {}", location, message);
+    ZirconError::Panic(format!("{} at {}", message, location))
+}
+
+/// Calls `f` and polls the future it returns, turning a panic in either into `ZirconError::Panic`,
+/// so that one request doesn't take down the event loop serving the others.
+///
+/// The state the handler was modifying may be left inconsistent, as with any caught panic.
+/// Nothing is caught if the crate is built with `panic = "abort"`.
+pub fn catch_panic<F: FnOnce() -> HandlerResult>(f: F) -> HandlerResult {
+    install_hook();
+
+    let result = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(x) => x,
+        Err(payload) => return Err(panic_error(payload)).into_future().boxed(),
+    };
+
+    Box::new(AssertUnwindSafe(result).catch_unwind().then(|result| {
+        match result {
+            Ok(x) => x,
+            Err(payload) => Err(panic_error(payload)),
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future;
+
+    fn panic_message(result: HandlerResult) -> String {
+        match result.wait() {
+            Err(ZirconError::Panic(message)) => message,
+            _ => panic!("ZirconError::Panic is expected"),
+        }
+    }
+
+    #[test]
+    fn sync_panic() {
+        let message = panic_message(catch_panic(|| panic!("boom {}", 1)));
+        assert!(message.starts_with("boom 1 at "), "{}", message);
+        assert!(message.contains("unwind.rs"), "{}", message);
+    }
+
+    #[test]
+    fn async_panic() {
+        let message = panic_message(catch_panic(|| {
+            Box::new(future::lazy(|| -> Result<Response, ZirconError> { panic!("later") }))
+        }));
+        assert!(message.starts_with("later at "), "{}", message);
+    }
+
+    #[test]
+    fn no_panic() {
+        assert!(catch_panic(|| Response::text("ok").render()).wait().is_ok());
+        match catch_panic(|| ZirconError::render_error_status(StatusCode::NotFound)).wait() {
+            Err(ZirconError::Status(StatusCode::NotFound)) => (),
+            _ => panic!("404 is expected"),
+        }
+    }
+}
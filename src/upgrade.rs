@@ -0,0 +1,230 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use futures::future::Either;
+use futures::{Future, Stream};
+use libc;
+use tokio_core::reactor::Core;
+use tokio_signal::unix::{Signal, SIGUSR2};
+
+use listener::Listener;
+use shutdown::Shutdown;
+
+/// Comma separated listener fds handed over to the new process.
+const UPGRADE_FDS: &'static str = "ZIRCON_UPGRADE_FDS";
+/// The fd the new process writes to when it is ready.
+const UPGRADE_READY_FD: &'static str = "ZIRCON_UPGRADE_READY_FD";
+
+fn cvt(ret: libc::c_int) -> io::Result<libc::c_int> {
+    if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(ret)
+    }
+}
+
+/// Sets close-on-exec on `fd`, so that a child process doesn't inherit it.
+pub fn set_cloexec(fd: RawFd) -> io::Result<()> {
+    cvt(unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) }).map(|_| ())
+}
+
+/// Fd is a file descriptor closed on drop.
+struct Fd(RawFd);
+
+impl Fd {
+    /// Duplicates `fd` without close-on-exec, so that a child process inherits it.
+    fn dup_inheritable(fd: RawFd) -> io::Result<Fd> {
+        cvt(unsafe { libc::dup(fd) }).map(Fd)
+    }
+
+    fn into_file(self) -> File {
+        let file = unsafe { File::from_raw_fd(self.0) };
+        mem::forget(self);
+        file
+    }
+}
+
+impl Drop for Fd {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.0); }
+    }
+}
+
+/// Makes a pipe whose ends are both close-on-exec.
+fn pipe() -> io::Result<(Fd, Fd)> {
+    let mut fds = [0; 2];
+    cvt(unsafe { libc::pipe(fds.as_mut_ptr()) })?;
+    let (reader, writer) = (Fd(fds[0]), Fd(fds[1]));
+    set_cloexec(reader.0)?;
+    set_cloexec(writer.0)?;
+    Ok((reader, writer))
+}
+
+/// Waits until the new process writes to the ready pipe.
+fn wait_ready(mut reader: File, timeout: Duration) -> io::Result<()> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = [0u8; 1];
+        let _ = tx.send(reader.read(&mut buf));
+    });
+
+    match rx.recv_timeout(timeout) {
+        Ok(Ok(1)) => Ok(()),
+        Ok(Ok(_)) => Err(io::Error::new(io::ErrorKind::Other, "new process exited before it got ready")),
+        Ok(Err(err)) => Err(err),
+        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "new process didn't get ready in time")),
+    }
+}
+
+struct Inner {
+    listeners: Vec<Listener>,
+    shutdown: Shutdown,
+    timeout: Duration,
+    in_progress: Mutex<()>,
+    handed_over: Arc<AtomicBool>,
+}
+
+/// UpgradeHandle replaces the running server with a new process without dropping connections.
+///
+/// `upgrade()` starts the current executable again with the same arguments, and hands the listening
+/// sockets to it. The new process takes them in `Zircon::bind*()` instead of binding, and reports
+/// readiness once it serves. Then this server stops accepting and drains as on shutdown.
+#[derive(Clone)]
+pub struct UpgradeHandle {
+    inner: Arc<Inner>,
+}
+
+impl UpgradeHandle {
+    pub fn new(listeners: Vec<Listener>, shutdown: Shutdown, timeout: Duration,
+               handed_over: Arc<AtomicBool>) -> UpgradeHandle {
+        UpgradeHandle {
+            inner: Arc::new(Inner {
+                listeners: listeners,
+                shutdown: shutdown,
+                timeout: timeout,
+                in_progress: Mutex::new(()),
+                handed_over: handed_over,
+            }),
+        }
+    }
+
+    /// Starts the new process and waits until it gets ready.
+    /// If it doesn't get ready in `ZirconConfig::upgrade_timeout()`, it is killed,
+    /// the error is returned, and this server keeps serving.
+    pub fn upgrade(&self) -> io::Result<()> {
+        let _lock = match self.inner.in_progress.try_lock() {
+            Ok(x) => x,
+            Err(_) => return Err(io::Error::new(io::ErrorKind::Other, "upgrade is already in progress")),
+        };
+        if self.inner.shutdown.is_shutdown() {
+            return Err(io::Error::new(io::ErrorKind::Other, "server is shutting down"));
+        }
+
+        let (ready_reader, ready_writer) = pipe()?;
+        let child_ready_writer = Fd::dup_inheritable(ready_writer.0)?;
+        drop(ready_writer);
+
+        let mut child_fds = Vec::new();
+        for listener in &self.inner.listeners {
+            child_fds.push(Fd::dup_inheritable(listener.as_raw_fd())?);
+        }
+        let fd_list = child_fds.iter().map(|fd| fd.0.to_string()).collect::<Vec<_>>().join(",");
+
+        let mut child = Command::new(env::current_exe()?)
+            .args(env::args_os().skip(1))
+            .env(UPGRADE_FDS, fd_list)
+            .env(UPGRADE_READY_FD, child_ready_writer.0.to_string())
+            .spawn()?;
+        // Close our copies, so that reading the pipe sees EOF if the child exits.
+        drop(child_fds);
+        drop(child_ready_writer);
+        info!("started new process {} for upgrade", child.id());
+
+        match wait_ready(ready_reader.into_file(), self.inner.timeout) {
+            Ok(()) => {
+                info!("new process {} is ready; shutting down", child.id());
+                self.inner.handed_over.store(true, Ordering::SeqCst);
+                self.inner.shutdown.shutdown();
+                Ok(())
+            },
+            Err(err) => {
+                error!("upgrade failed: {}", err);
+                let _ = child.kill();
+                let _ = child.wait();
+                Err(err)
+            }
+        }
+    }
+}
+
+/// Takes the listening sockets handed over by the previous process on upgrade.
+/// None is returned if this process is not started by an upgrade.
+pub fn listen_fds() -> io::Result<Option<Vec<Listener>>> {
+    let fds = match env::var(UPGRADE_FDS) {
+        Ok(x) => x,
+        Err(_) => return Ok(None),
+    };
+    env::remove_var(UPGRADE_FDS);
+
+    let mut listeners = Vec::new();
+    for fd in fds.split(',').filter(|s| !s.is_empty()) {
+        let fd: RawFd = match fd.parse() {
+            Ok(x) => x,
+            Err(_) => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                                format!("{} is malformed: {}", UPGRADE_FDS, fds))),
+        };
+        set_cloexec(fd)?;
+        listeners.push(unsafe { Listener::from_raw_fd(fd)? });
+    }
+
+    Ok(Some(listeners))
+}
+
+/// Tells the previous process that this process serves now. Does nothing if this process
+/// is not started by an upgrade.
+pub fn notify_ready() {
+    let fd = match env::var(UPGRADE_READY_FD).ok().and_then(|x| x.parse::<RawFd>().ok()) {
+        Some(x) => x,
+        None => return,
+    };
+    env::remove_var(UPGRADE_READY_FD);
+
+    let mut file = Fd(fd).into_file();
+    if let Err(err) = file.write_all(b"1") {
+        warn!("failed to notify readiness to the previous process: {}", err);
+    }
+}
+
+/// Spawns a thread that runs `upgrade` on every SIGUSR2 until shutdown.
+pub fn upgrade_on_signal(upgrade: UpgradeHandle, shutdown: Shutdown) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut core = match Core::new() {
+            Ok(x) => x,
+            Err(err) => {
+                error!("failed to listen to SIGUSR2: {}", err);
+                return;
+            }
+        };
+
+        let signals = Signal::new(SIGUSR2, &core.handle()).flatten_stream().for_each(move |_| {
+            info!("received SIGUSR2; upgrading");
+            // The error is already logged.
+            let _ = upgrade.upgrade();
+            Ok(())
+        });
+
+        match core.run(signals.select2(shutdown.wait())) {
+            Err(Either::A((err, _))) => error!("failed to listen to SIGUSR2: {}", err),
+            _ => (),
+        }
+    })
+}
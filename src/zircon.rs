@@ -1,28 +1,126 @@
+use std::cell::Cell;
+use std::fs;
+use std::io;
 use std::marker::PhantomData;
+use std::mem;
 use std::net::SocketAddr;
+use std::net;
+use std::path::PathBuf;
+use std::rc::Rc;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
 use std::thread;
+use std::time::Duration;
 
 use futures::Stream;
+use futures::future::{self, Either};
 use futures::{Future, IntoFuture};
+use hyper::header::Connection;
 use hyper::server::Http;
 use hyper;
-use net2::unix::UnixTcpBuilderExt;
-use net2;
 use tokio_core::net::TcpListener;
-use tokio_core::reactor::Core;
+use tokio_core::reactor::{Core, Handle, Interval, Timeout};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_rustls::ServerConfigExt;
+use tokio_uds::UnixListener;
 
 use DefaultErrorHandler;
 use ErrorHandler;
 use HyperRequest;
 use HyperResponse;
+use access_log;
+use connection::{Activity, ActivityIo, ConnectionGuard, ConnectionLimit, IdleWatchdog};
 use error;
+use listener::{ListenAddr, Listener, UnixListenAddr};
 use prelude::*;
+use shutdown::Shutdown;
+use systemd;
+use tls::TlsConfig;
+use unwind;
+use upgrade::{self, UpgradeHandle};
 
+/// Drain tracks requests in flight on one accept thread, so that shutdown can wait for them.
+struct Drain {
+    in_flight: Cell<usize>,
+    draining: Cell<bool>,
+}
+
+impl Drain {
+    fn new() -> Drain {
+        Drain {
+            in_flight: Cell::new(0),
+            draining: Cell::new(false),
+        }
+    }
+
+    fn in_flight(&self) -> usize {
+        self.in_flight.get()
+    }
+
+    fn is_draining(&self) -> bool {
+        self.draining.get()
+    }
+}
+
+/// RequestGuard marks a request in flight until the request finishes or is dropped.
+struct RequestGuard {
+    drain: Rc<Drain>,
+    activity: Rc<Activity>,
+}
+
+impl RequestGuard {
+    fn new(drain: Rc<Drain>, activity: Rc<Activity>) -> RequestGuard {
+        drain.in_flight.set(drain.in_flight.get() + 1);
+        activity.start_request();
+        RequestGuard {
+            drain: drain,
+            activity: activity,
+        }
+    }
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.activity.finish_request();
+        self.drain.in_flight.set(self.drain.in_flight.get() - 1);
+    }
+}
+
+/// ZirconService serves the requests of one connection.
 struct ZirconService<A: ZirconApp, H: Handler<A>, E: ErrorHandler<A>> {
     app: Arc<A>,
     handler: Arc<H>,
     error_handler: Arc<E>,
+    handle: Handle,
+    drain: Rc<Drain>,
+    activity: Rc<Activity>,
+    remote_addr: Option<SocketAddr>,
+    /// True if the connection is over TLS.
+    secure: bool,
+}
+
+/// Makes `resp` fail with `status` when it doesn't finish within `timeout`.
+fn with_handler_timeout(resp: HandlerResult, timeout: Duration, status: StatusCode, handle: &Handle) -> HandlerResult {
+    let timer = match Timeout::new(timeout, handle) {
+        Ok(x) => x,
+        Err(err) => {
+            error!("failed to set handler timeout: {}", err);
+            return resp;
+        }
+    };
+
+    Box::new(resp.select2(timer).then(|result| {
+        match result {
+            Ok(Either::A((resp, _))) => Ok(resp),
+            Ok(Either::B(_)) => {
+                warn!("handler timed out");
+                Err(ZirconError::Status(status))
+            },
+            Err(Either::A((err, _))) => Err(err),
+            Err(Either::B((err, _))) => Err(ZirconError::IoError(err)),
+        }
+    }))
 }
 
 impl<A: ZirconApp, H: Handler<A>, E: ErrorHandler<A>> hyper::server::Service for ZirconService<A, H, E> {
@@ -35,9 +133,31 @@ impl<A: ZirconApp, H: Handler<A>, E: ErrorHandler<A>> hyper::server::Service for
         // Need to clone self.data because of lifetime.
         // If this works without clone, it is good.
 
-        let req = Request::from_internal(hyper_request);
+        let guard = RequestGuard::new(self.drain.clone(), self.activity.clone());
+        let mut req = Request::from_internal(hyper_request);
+        req.header.set_peer_addr(self.remote_addr);
+        req.header.secure = self.secure;
+        let config = self.app.config();
+        req.header.respect_xforwarded = config.respect_xforwarded();
+        req.header.trusted_proxies = config.trusted_proxies().clone();
+        req.header.forwarded_headers = config.forwarded_headers();
+        if let Some(timeout) = config.body_read_timeout() {
+            if let Err(err) = req.body.set_read_timeout(timeout, &self.handle) {
+                error!("failed to set body read timeout: {}", err);
+            }
+        }
+        let log = config.access_log().and_then(|log| access_log::start(log, &mut req));
+
+        // A panic in the handler becomes 500 here, instead of unwinding through the event loop.
+        let app = self.app.clone();
+        let handler = self.handler.clone();
+        let x0 = unwind::catch_panic(move || handler.handle(app, req));
+        let x0 = match config.handler_timeout() {
+            Some(timeout) => with_handler_timeout(x0, timeout, config.handler_timeout_status(), &self.handle),
+            None => x0,
+        };
 
-        let x1 = self.handler.handle(self.app.clone(), req).map(move |resp| {
+        let x1 = x0.map(move |resp| {
             resp.origin
         });
 
@@ -45,25 +165,316 @@ impl<A: ZirconApp, H: Handler<A>, E: ErrorHandler<A>> hyper::server::Service for
         let a2 = self.app.clone();
         let e2 = self.error_handler.clone();
         let x2 = x1.or_else(move |err| {
-            e2.handle(a2, &err).map(|resp| {
+            unwind::catch_panic(move || e2.handle(a2, &err)).map(|resp| {
                 resp.origin
             }).or_else(|_err2| {
                 Ok(error::make_fallback_error_response().origin)
             })
         });
 
+        // While draining, ask the client to close the connection so that
+        // keep-alive connections don't outlive the server.
+        // The access log sees the final response, including what the error handler made.
+        let handle = self.handle.clone();
+        let x3 = x2.map(move |mut resp| {
+            if guard.drain.is_draining() {
+                resp.headers_mut().set(Connection::close());
+            }
+            drop(guard);
+            match log {
+                Some(log) => log.finish(resp, &handle),
+                None => resp,
+            }
+        });
+
         // Hmm, Box::new() works but .boxed() doesn't work.
         // See https://github.com/alexcrichton/futures-rs/issues/363
-        Box::new(x2.into_future())
+        Box::new(x3.into_future())
+    }
+}
+
+/// Worker is the serving state of one accept thread.
+struct Worker<A: ZirconApp, H: Handler<A>, E: ErrorHandler<A>> {
+    app: Arc<A>,
+    handler: Arc<H>,
+    error_handler: Arc<E>,
+    handle: Handle,
+    protocol: Arc<Http>,
+    drain: Rc<Drain>,
+    limit: ConnectionLimit,
+}
+
+impl<A: ZirconApp, H: Handler<A>, E: ErrorHandler<A>> Worker<A, H, E> {
+    /// Takes a connection slot. When the limit is reached, None is returned and the connection should be closed.
+    fn acquire(&self) -> Option<ConnectionGuard> {
+        let guard = self.limit.acquire();
+        if guard.is_none() {
+            warn!("closing a new connection: too many connections ({})", self.limit.count());
+        }
+        guard
+    }
+
+    /// Spawns a task serving http on `io` until the connection is closed or becomes idle for too long.
+    fn serve_connection<I>(&self, io: I, remote_addr: Option<SocketAddr>, secure: bool, guard: ConnectionGuard)
+    where I: AsyncRead + AsyncWrite + 'static {
+        let config = self.app.config();
+        let activity = Rc::new(Activity::new());
+        let service = ZirconService {
+            app: self.app.clone(),
+            handler: self.handler.clone(),
+            error_handler: self.error_handler.clone(),
+            handle: self.handle.clone(),
+            drain: self.drain.clone(),
+            activity: activity.clone(),
+            remote_addr: remote_addr,
+            secure: secure,
+        };
+
+        let io = ActivityIo::new(io, activity.clone());
+        let conn = self.protocol.serve_connection(io, service).map(|_| ()).map_err(|err| {
+            debug!("connection error: {}", err);
+        });
+
+        let watchdog = IdleWatchdog::new(activity, config.header_read_timeout(), config.keep_alive_timeout(),
+                                         &self.handle);
+        let conn: Box<Future<Item=(), Error=()>> = match watchdog {
+            Ok(Some(watchdog)) => Box::new(conn.select2(watchdog).then(|result| {
+                if let Ok(Either::B(_)) = result {
+                    debug!("closing an idle connection");
+                }
+                Ok::<(), ()>(())
+            })),
+            Ok(None) => Box::new(conn),
+            Err(err) => {
+                error!("failed to set connection timeout: {}", err);
+                Box::new(conn)
+            },
+        };
+
+        self.handle.spawn(conn.then(move |result| {
+            drop(guard);
+            result
+        }));
+    }
+}
+
+/// Runs accept loops for `listeners` on a new Core until shutdown is requested, then drains.
+/// Whether the Core has started is reported through `ready`.
+///
+/// If an accept loop stops by itself, the whole server is shut down and an error is returned,
+/// since the listener would otherwise be left without anyone accepting on it.
+fn serve<A: ZirconApp, H: Handler<A>, E: ErrorHandler<A>>(listeners: Vec<Listener>, protocol: Arc<Http>,
+                                                          app: Arc<A>, handler: Arc<H>, error_handler: Arc<E>,
+                                                          shutdown: Shutdown, ready: mpsc::Sender<io::Result<()>>)
+                                                          -> io::Result<()> {
+    let mut core = match Core::new() {
+        Ok(core) => core,
+        Err(err) => {
+            let _ = ready.send(Err(err));
+            return Ok(());
+        }
+    };
+
+    let drain = Rc::new(Drain::new());
+    let shutdown_timeout = app.config().shutdown_timeout();
+    let worker = Rc::new(Worker {
+        limit: ConnectionLimit::new(app.config().max_connections_per_thread()),
+        app: app,
+        handler: handler,
+        error_handler: error_handler,
+        handle: core.handle(),
+        protocol: protocol,
+        drain: drain.clone(),
+    });
+
+    let mut accepts: Vec<Box<Future<Item=(), Error=io::Error>>> = Vec::new();
+    for listener in listeners {
+        match accept(listener, worker.clone()) {
+            Ok(x) => accepts.push(x),
+            Err(err) => {
+                let _ = ready.send(Err(err));
+                return Ok(());
+            }
+        }
+    }
+    let _ = ready.send(Ok(()));
+
+    // The accept loops and their listeners are dropped at the end of this statement, before draining.
+    // Once every accept thread has dropped its copy, new connections are refused.
+    let result = match core.run(future::select_all(accepts).select2(shutdown.wait())) {
+        Ok(Either::A(_)) => Err(io::Error::new(io::ErrorKind::Other, "listener is closed")),
+        Err(Either::A(((err, _, _), _))) => Err(err),
+        Ok(Either::B(_)) | Err(Either::B(_)) => Ok(()),
+    };
+    if let Err(ref err) = result {
+        error!("stopped accepting connections: {}; shutting down", err);
+        shutdown.shutdown();
+    }
+
+    drain.draining.set(true);
+    if drain.in_flight() == 0 {
+        return result;
+    }
+
+    info!("waiting for {} requests in flight", drain.in_flight());
+    match wait_for_drain(&mut core, drain.clone(), shutdown_timeout) {
+        Ok(true) => (),
+        Ok(false) => warn!("dropping {} requests in flight after shutdown timeout", drain.in_flight()),
+        Err(err) => error!("failed to wait for requests in flight: {}", err),
+    }
+    result
+}
+
+/// How long accepting pauses after an accept error.
+const ACCEPT_ERROR_BACKOFF_MILLIS: u64 = 100;
+
+/// Calls `f` with every connection from `incoming`.
+///
+/// An accept error, e.g. EMFILE when the process runs out of file descriptors, is logged and accepting
+/// pauses for a while, instead of ending the loop. Connections are closed in the meantime, which frees
+/// file descriptors, and the pending connections stay in the backlog.
+fn accept_loop<S, F>(incoming: S, name: String, handle: Handle, mut f: F) -> Box<Future<Item=(), Error=io::Error>>
+where S: Stream<Error=io::Error> + 'static, S::Item: 'static, F: FnMut(S::Item) + 'static {
+    let accepted = incoming.then(move |result| -> Box<Future<Item=Option<S::Item>, Error=io::Error>> {
+        let err = match result {
+            Ok(x) => return Box::new(future::ok(Some(x))),
+            Err(err) => err,
+        };
+
+        error!("failed to accept on {}: {}", name, err);
+        match Timeout::new(Duration::from_millis(ACCEPT_ERROR_BACKOFF_MILLIS), &handle) {
+            Ok(timer) => Box::new(timer.map(|_| None)),
+            Err(err) => {
+                error!("failed to pause accepting on {}: {}", name, err);
+                Box::new(future::ok(None))
+            },
+        }
+    });
+
+    Box::new(accepted.for_each(move |x| {
+        if let Some(x) = x {
+            f(x);
+        }
+        Ok(())
+    }))
+}
+
+/// Makes an accept loop that serves every connection accepted on `listener`.
+fn accept<A: ZirconApp, H: Handler<A>, E: ErrorHandler<A>>(listener: Listener, worker: Rc<Worker<A, H, E>>)
+                                                           -> io::Result<Box<Future<Item=(), Error=io::Error>>> {
+    let handle = worker.handle.clone();
+    match listener {
+        Listener::Tcp(listener) => {
+            let addr = listener.local_addr()?;
+            let listener = TcpListener::from_listener(listener, &addr, &handle)?;
+            Ok(accept_loop(listener.incoming(), addr.to_string(), handle, move |(socket, remote_addr)| {
+                if let Some(guard) = worker.acquire() {
+                    worker.serve_connection(socket, Some(remote_addr), false, guard);
+                }
+            }))
+        },
+        Listener::Tls(listener, config) => {
+            let addr = listener.local_addr()?;
+            let listener = TcpListener::from_listener(listener, &addr, &handle)?;
+            let h2 = handle.clone();
+            Ok(accept_loop(listener.incoming(), addr.to_string(), handle, move |(socket, remote_addr)| {
+                let guard = match worker.acquire() {
+                    Some(x) => x,
+                    None => return,
+                };
+
+                // The handshake is bounded by the header read timeout, too.
+                // Without the timer, only this connection is dropped.
+                let timer = match worker.app.config().header_read_timeout() {
+                    Some(timeout) => match Timeout::new(timeout, &h2) {
+                        Ok(x) => Some(x),
+                        Err(err) => {
+                            error!("failed to set handshake timeout for {}: {}", remote_addr, err);
+                            return;
+                        },
+                    },
+                    None => None,
+                };
+                let handshake: Box<Future<Item=_, Error=io::Error>> = match timer {
+                    Some(timer) => Box::new(config.accept_async(socket).select2(timer).then(|result| {
+                        match result {
+                            Ok(Either::A((tls_socket, _))) => Ok(tls_socket),
+                            Ok(Either::B(_)) => Err(io::Error::new(io::ErrorKind::TimedOut, "handshake timed out")),
+                            Err(Either::A((err, _))) | Err(Either::B((err, _))) => Err(err),
+                        }
+                    })),
+                    None => Box::new(config.accept_async(socket)),
+                };
+
+                let w2 = worker.clone();
+                h2.spawn(handshake.map(move |tls_socket| {
+                    w2.serve_connection(tls_socket, Some(remote_addr), true, guard);
+                }).map_err(move |err| {
+                    debug!("TLS handshake with {} failed: {}", remote_addr, err);
+                }));
+            }))
+        },
+        Listener::Unix(listener) => {
+            // Unix sockets have no SocketAddr for the peer, so the connection is served
+            // without one, and Request::peer_addr() returns None.
+            let path = listener.local_addr()?.as_pathname().map(|p| p.display().to_string()).unwrap_or_default();
+            let listener = UnixListener::from_listener(listener, &handle)?;
+            Ok(accept_loop(listener.incoming(), path, handle, move |(socket, _peer)| {
+                if let Some(guard) = worker.acquire() {
+                    worker.serve_connection(socket, None, false, guard);
+                }
+            }))
+        },
+    }
+}
+
+/// Runs `core` until every request in flight finishes or `timeout` elapses.
+/// Returns false if the timeout elapsed first.
+fn wait_for_drain(core: &mut Core, drain: Rc<Drain>, timeout: Duration) -> io::Result<bool> {
+    let handle = core.handle();
+    let finished = Interval::new(Duration::from_millis(10), &handle)?
+        .take_while(move |_| Ok(drain.in_flight() > 0))
+        .for_each(|_| Ok(()));
+    let deadline = Timeout::new(timeout, &handle)?;
+
+    match core.run(finished.select2(deadline)) {
+        Ok(Either::A(_)) => Ok(true),
+        Ok(Either::B(_)) => Ok(false),
+        Err(Either::A((err, _))) | Err(Either::B((err, _))) => Err(err),
+    }
+}
+
+/// Takes listening sockets passed from the parent process, by an upgrade or by systemd socket activation.
+/// The second value is true for an upgrade, where the socket files are owned by this process from now on.
+fn inherited_listeners() -> io::Result<Option<(Vec<Listener>, bool)>> {
+    if let Some(listeners) = upgrade::listen_fds()? {
+        info!("serving {} sockets handed over by the previous process", listeners.len());
+        return Ok(Some((listeners, true)));
     }
+    if let Some(listeners) = systemd::listen_fds()? {
+        info!("serving {} sockets passed by systemd", listeners.len());
+        return Ok(Some((listeners, false)));
+    }
+    Ok(None)
 }
 
 // ----------------------------------------------------------------------
 
+/// Zircon serves `handler` for `app`. Errors of the handler are turned into responses by the error handler.
+///
+/// A panic in the handler doesn't take down the server. It is caught, and the request fails with
+/// `ZirconError::Panic`, which is passed to the error handler like other errors, so a custom one can
+/// render its own 500 page. `DefaultErrorHandler` responds with an empty 500. If the error handler
+/// panics too, an empty 500 is returned.
+///
+/// To log where a panic happened, a panic hook is installed the first time a request is served.
+/// It calls the hook that was set before, e.g. the default one printing to stderr, so set a custom hook
+/// before serving. A hook set after that replaces this one, and the location is logged as unknown.
 pub struct Zircon<A: ZirconApp, H: Handler<A>, E: ErrorHandler<A>> {
     app: Arc<A>,
     handler: Arc<H>,
     error_handler: Arc<E>,
+    shutdown: Shutdown,
 }
 
 impl<A: ZirconApp, H: Handler<A>> Zircon<A, H, DefaultErrorHandler<A>> {
@@ -78,50 +489,435 @@ impl<A: ZirconApp, H: Handler<A>> Zircon<A, H, DefaultErrorHandler<A>> {
     }
 }
 
-fn serve<A: ZirconApp, H: Handler<A>, E: ErrorHandler<A>>(addr: SocketAddr, protocol: Arc<Http>,
-                                                          app: Arc<A>, handler: Arc<H>, error_handler: Arc<E>) {
-    let mut core = Core::new().unwrap();
-    let handle = core.handle();
-
-    let listener = net2::TcpBuilder::new_v4().unwrap()
-        .reuse_port(true).unwrap()
-        .bind(&addr).unwrap()
-        .listen(128).unwrap();
-    let listener = TcpListener::from_listener(listener, &addr, &handle).unwrap();
-
-    core.run(listener.incoming().for_each(|(socket, addr)| {
-        protocol.bind_connection(&handle, socket, addr, ZirconService {
-            app: app.clone(),
-            handler: handler.clone(),
-            error_handler: error_handler.clone(),
-        });
-        Ok(())
-    })).unwrap();
-}
-
 impl<A: ZirconApp, H: Handler<A>, E: ErrorHandler<A>> Zircon<A, H, E> {
     pub fn with_custom_error_handler(app: A, handler: H, error_handler: E) -> Zircon<A, H, E> {
         Zircon {
             app: Arc::new(app),
             handler: Arc::new(handler),
             error_handler: Arc::new(error_handler),
+            shutdown: Shutdown::new(),
         }
     }
 
-    pub fn http(self, addr: &SocketAddr) -> hyper::Result<()> {
+    /// Returns a handle to stop the server started by `http()`.
+    /// Take it before calling `http()`, since `http()` blocks until shutdown.
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Makes a Server serving `listeners`.
+    /// Socket files in `owned_paths` are removed when the server stops.
+    fn into_server(self, listeners: Vec<Listener>, owned_paths: Vec<PathBuf>) -> io::Result<Server<A, H, E>> {
+        if listeners.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "no address to listen on"));
+        }
+
+        let mut local_addrs = Vec::new();
+        let mut unix_paths = Vec::new();
+        for listener in &listeners {
+            match *listener {
+                Listener::Tcp(ref l) | Listener::Tls(ref l, _) => local_addrs.push(l.local_addr()?),
+                Listener::Unix(ref l) => {
+                    if let Some(path) = l.local_addr()?.as_pathname() {
+                        unix_paths.push(path.to_path_buf());
+                    }
+                },
+            }
+        }
+
+        Ok(Server {
+            app: self.app,
+            handler: self.handler,
+            error_handler: self.error_handler,
+            shutdown: self.shutdown,
+            listeners: listeners,
+            local_addrs: local_addrs,
+            unix_paths: unix_paths,
+            owned_paths: owned_paths,
+            handed_over: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Binds a listener on `addr` without serving yet.
+    /// Use port 0 to let the OS choose a port, and `Server::local_addr()` to learn it.
+    pub fn bind(self, addr: &SocketAddr) -> io::Result<Server<A, H, E>> {
+        self.bind_all(vec![ListenAddr::new(*addr)])
+    }
+
+    /// Binds listeners on all of `addrs` without serving yet.
+    /// Every accept thread serves every address.
+    ///
+    /// If this process is started by an upgrade or by systemd socket activation,
+    /// the sockets passed from the parent are served instead, and `addrs` are ignored.
+    pub fn bind_all<I, L>(self, addrs: I) -> io::Result<Server<A, H, E>>
+    where I: IntoIterator<Item=L>, L: Into<ListenAddr> {
+        if let Some((listeners, _)) = inherited_listeners()? {
+            return self.into_server(listeners, Vec::new());
+        }
+
+        let mut listeners = Vec::new();
+        for addr in addrs {
+            listeners.push(Listener::Tcp(addr.into().bind(self.app.config().listen_backlog())?));
+        }
+
+        self.into_server(listeners, Vec::new())
+    }
+
+    /// Serves already listening sockets without binding, e.g. ones inherited from the parent process.
+    pub fn bind_listeners<I: IntoIterator<Item=net::TcpListener>>(self, listeners: I) -> io::Result<Server<A, H, E>> {
+        let listeners = listeners.into_iter().map(Listener::Tcp).collect();
+        self.into_server(listeners, Vec::new())
+    }
+
+    /// Binds a TLS listener on `addr` without serving yet.
+    ///
+    /// If this process is started by an upgrade or by systemd socket activation,
+    /// the TCP sockets passed from the parent are served with TLS instead, and `addr` is ignored.
+    pub fn bind_https(self, addr: &SocketAddr, tls: TlsConfig) -> io::Result<Server<A, H, E>> {
+        let config = tls.server_config();
+        if let Some((listeners, _)) = inherited_listeners()? {
+            let mut tls_listeners = Vec::new();
+            for listener in listeners {
+                match listener {
+                    Listener::Tcp(l) => tls_listeners.push(Listener::Tls(l, config.clone())),
+                    _ => return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                                   "https can be served only on TCP sockets")),
+                }
+            }
+            return self.into_server(tls_listeners, Vec::new());
+        }
+
+        let listener = ListenAddr::new(*addr).bind(self.app.config().listen_backlog())?;
+        self.into_server(vec![Listener::Tls(listener, config)], Vec::new())
+    }
+
+    /// Binds a listener on the Unix domain socket `addr` without serving yet.
+    ///
+    /// If this process is started by an upgrade or by systemd socket activation,
+    /// the sockets passed from the parent are served instead, and `addr` is ignored.
+    pub fn bind_unix<L: Into<UnixListenAddr>>(self, addr: L) -> io::Result<Server<A, H, E>> {
+        if let Some((listeners, upgraded)) = inherited_listeners()? {
+            let mut owned_paths = Vec::new();
+            if upgraded {
+                for listener in &listeners {
+                    if let Listener::Unix(ref l) = *listener {
+                        if let Some(path) = l.local_addr()?.as_pathname() {
+                            owned_paths.push(path.to_path_buf());
+                        }
+                    }
+                }
+            }
+            return self.into_server(listeners, owned_paths);
+        }
+
+        let addr = addr.into();
+        let listener = addr.bind()?;
+        self.into_server(vec![Listener::Unix(listener)], vec![addr.path().to_path_buf()])
+    }
+
+    /// Serves http until shutdown is requested through `shutdown_handle()`.
+    pub fn http(self, addr: &SocketAddr) -> io::Result<()> {
+        self.bind(addr)?.run()
+    }
+
+    /// Serves http on all of `addrs` until shutdown is requested through `shutdown_handle()`.
+    pub fn http_all<I, L>(self, addrs: I) -> io::Result<()>
+    where I: IntoIterator<Item=L>, L: Into<ListenAddr> {
+        self.bind_all(addrs)?.run()
+    }
+
+    /// Serves https on `addr` until shutdown is requested through `shutdown_handle()`.
+    /// Keep a clone of `tls` to reload certificates while serving.
+    pub fn https(self, addr: &SocketAddr, tls: TlsConfig) -> io::Result<()> {
+        self.bind_https(addr, tls)?.run()
+    }
+
+    /// Serves http on the Unix domain socket `addr` until shutdown is requested through `shutdown_handle()`.
+    /// The socket file is removed when the server stops.
+    pub fn unix<L: Into<UnixListenAddr>>(self, addr: L) -> io::Result<()> {
+        self.bind_unix(addr)?.run()
+    }
+}
+
+// ----------------------------------------------------------------------
+
+/// Server is a Zircon bound to its listeners.
+pub struct Server<A: ZirconApp, H: Handler<A>, E: ErrorHandler<A>> {
+    app: Arc<A>,
+    handler: Arc<H>,
+    error_handler: Arc<E>,
+    shutdown: Shutdown,
+    listeners: Vec<Listener>,
+    local_addrs: Vec<SocketAddr>,
+    unix_paths: Vec<PathBuf>,
+    owned_paths: Vec<PathBuf>,
+    /// Set when the listeners are handed over to a new process by an upgrade.
+    handed_over: Arc<AtomicBool>,
+}
+
+impl<A: ZirconApp, H: Handler<A>, E: ErrorHandler<A>> Server<A, H, E> {
+    /// Returns the TCP address the server is actually bound to.
+    /// When bound to several addresses, this is the first one.
+    /// None is returned when the server listens only on Unix domain sockets.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addrs.first().cloned()
+    }
+
+    /// Returns all the TCP addresses the server is actually bound to.
+    pub fn local_addrs(&self) -> &[SocketAddr] {
+        &self.local_addrs
+    }
+
+    /// Returns the paths of the Unix domain sockets the server is bound to.
+    pub fn unix_paths(&self) -> &[PathBuf] {
+        &self.unix_paths
+    }
+
+    /// Returns a handle to stop the server.
+    pub fn shutdown_handle(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Returns a handle to replace the server with a new process without dropping connections.
+    ///
+    /// The handle keeps copies of the listening sockets, so the ports are open until it is dropped.
+    /// Drop it on shutdown, or new connections wait in the backlog instead of being refused.
+    pub fn upgrade_handle(&self) -> io::Result<UpgradeHandle> {
+        let mut listeners = Vec::new();
+        for listener in &self.listeners {
+            listeners.push(listener.try_clone()?);
+        }
+
+        Ok(UpgradeHandle::new(listeners, self.shutdown.clone(), self.app.config().upgrade_timeout(),
+                              self.handed_over.clone()))
+    }
+
+    /// Serves http until shutdown is requested.
+    /// Returns an error if any accept thread fails to start, or if accepting on a listener stops
+    /// by itself, in which case the server is shut down.
+    pub fn run(mut self) -> io::Result<()> {
         // Example taken from https://gist.github.com/alexcrichton/7b97beda66d5e9b10321207cd69afbbc
         let protocol = Arc::new(Http::new());
+        let num_threads = ::std::cmp::max(self.app.num_accept_threads(), 1);
+
+        // The copies for an upgrade are made before the listeners are given away.
+        let upgrade_handle = if self.app.config().upgrade_on_signal() { Some(self.upgrade_handle()) } else { None };
 
-        for _ in 0..self.app.num_accept_threads() {
-            let addr = addr.clone();
+        // Duplicate the listeners up front, so that errors are reported on this thread.
+        // The originals go to the last thread, so that no copy is left here, and the sockets are closed
+        // as soon as the accept threads stop on shutdown.
+        let mut listeners_per_thread = Vec::new();
+        for _ in 1..num_threads {
+            let mut listeners = Vec::new();
+            for listener in &self.listeners {
+                listeners.push(listener.try_clone()?);
+            }
+            listeners_per_thread.push(listeners);
+        }
+        listeners_per_thread.push(mem::replace(&mut self.listeners, Vec::new()));
+
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let mut threads = Vec::new();
+        for listeners in listeners_per_thread {
             let app = self.app.clone();
             let handler = self.handler.clone();
             let error_handler = self.error_handler.clone();
             let p = protocol.clone();
-            thread::spawn(move || serve(addr.clone(), p.clone(), app.clone(), handler.clone(), error_handler.clone()));
+            let shutdown = self.shutdown.clone();
+            let ready = ready_tx.clone();
+            threads.push(thread::spawn(move || serve(listeners, p, app, handler, error_handler, shutdown, ready)));
         }
+        drop(ready_tx);
 
-        serve(addr.clone(), protocol.clone(), self.app.clone(), self.handler.clone(), self.error_handler.clone());
-        Ok(())
+        let mut result = Ok(());
+        for _ in 0..num_threads {
+            match ready_rx.recv() {
+                Ok(Ok(())) => (),
+                Ok(Err(err)) => {
+                    if result.is_ok() {
+                        result = Err(err);
+                    }
+                },
+                Err(_) => {
+                    if result.is_ok() {
+                        result = Err(io::Error::new(io::ErrorKind::Other, "accept thread exited before starting"));
+                    }
+                },
+            }
+        }
+
+        let mut signal_thread = None;
+        if result.is_ok() {
+            match upgrade_handle {
+                Some(Ok(x)) => signal_thread = Some(upgrade::upgrade_on_signal(x, self.shutdown.clone())),
+                Some(Err(err)) => result = Err(err),
+                None => (),
+            }
+        }
+
+        if result.is_err() {
+            self.shutdown.shutdown();
+        } else {
+            upgrade::notify_ready();
+            for addr in &self.local_addrs {
+                info!("listening on {}", addr);
+            }
+            for path in &self.unix_paths {
+                info!("listening on {}", path.display());
+            }
+        }
+
+        for t in threads {
+            match t.join() {
+                Ok(Ok(())) => (),
+                Ok(Err(err)) => {
+                    if result.is_ok() {
+                        result = Err(err);
+                    }
+                },
+                Err(_) => error!("accept thread panicked"),
+            }
+        }
+        if let Some(t) = signal_thread {
+            let _ = t.join();
+        }
+
+        // After an upgrade, the socket files belong to the new process.
+        let owned_paths = if self.handed_over.load(Ordering::SeqCst) { &[][..] } else { &self.owned_paths[..] };
+        for path in owned_paths {
+            if let Err(err) = fs::remove_file(path) {
+                warn!("failed to remove {}: {}", path.display(), err);
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ascii::AsciiExt;
+    use std::env;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::process;
+    use std::sync::Mutex;
+    use std::time::Instant;
+    use futures::sync::oneshot;
+    use serde_json::{self, Value as Json};
+    use access_log::{AccessLog, LogFormat, RotatingFile};
+
+    type App = ZirconDefaultApp<()>;
+
+    #[test]
+    fn refuse_connections_while_draining() {
+        let (started_tx, started_rx) = mpsc::channel();
+        let (release_tx, release_rx) = oneshot::channel::<()>();
+        let started_tx = Mutex::new(started_tx);
+        let release_rx = Mutex::new(Some(release_rx));
+        let handler = move |_app: Arc<App>, _req: Request| -> HandlerResult {
+            let _ = started_tx.lock().unwrap().send(());
+            let release = release_rx.lock().unwrap().take().unwrap();
+            Box::new(release.then(|_| Response::text("done").render()))
+        };
+
+        let app = ZirconDefaultApp::<()>::from_config(ZirconConfig::dev().with_num_accept_threads(2));
+        let server = Zircon::new(app, handler).bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let server = thread::spawn(move || server.run());
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        started_rx.recv().unwrap();
+
+        // The request is still in flight, so the server is draining, but the port is closed.
+        shutdown.shutdown();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            match TcpStream::connect(addr) {
+                Err(ref e) if e.kind() == io::ErrorKind::ConnectionRefused => break,
+                _ => {
+                    assert!(Instant::now() < deadline, "connection is not refused while draining");
+                    thread::sleep(Duration::from_millis(10));
+                },
+            }
+        }
+
+        release_tx.send(()).unwrap();
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK"), "{}", resp);
+        assert!(resp.to_ascii_lowercase().contains("connection: close"), "{}", resp);
+        assert!(server.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn access_log_sees_final_response() {
+        let path = env::temp_dir().join(format!("zircon-access-{}-final.log", process::id()));
+        let _ = fs::remove_file(&path);
+        let file = RotatingFile::open(&path, 1 << 20, 0).unwrap();
+        let log = AccessLog::new().with_format(LogFormat::Json).with_file(file);
+
+        let mut router = Router::new();
+        router.get("/fail", |_app: Arc<App>, _req: Request| -> HandlerResult {
+            ZirconError::render_error_status(StatusCode::BadRequest)
+        });
+        router.get("/panic", |_app: Arc<App>, _req: Request| -> HandlerResult {
+            panic!("boom")
+        });
+        router.get("/stream", |_app: Arc<App>, _req: Request| -> HandlerResult {
+            let mut resp = Response::new();
+            resp.origin.set_body("stream");
+            resp.render()
+        });
+        // The error handler hides what went wrong, except for 404.
+        let error_handler = |_app: Arc<App>, err: &ZirconError| -> HandlerResult {
+            match err.status() {
+                StatusCode::NotFound => Response::text("not found").with_status(StatusCode::NotFound).render(),
+                _ => Response::text("error").with_status(StatusCode::InternalServerError).render(),
+            }
+        };
+
+        let app = ZirconDefaultApp::<()>::from_config(ZirconConfig::dev().with_access_log(Some(log)));
+        let server = Zircon::with_custom_error_handler(app, router, error_handler);
+        let server = server.bind(&"127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = server.local_addr().unwrap();
+        let shutdown = server.shutdown_handle();
+        let server = thread::spawn(move || server.run());
+
+        let requests = [("/fail", "abc-1"), ("/panic", "abc-2"), ("/missing", "abc\"\n3"), ("/stream", "abc-4")];
+        for &(path, request_id) in &requests {
+            let mut client = TcpStream::connect(addr).unwrap();
+            write!(client, "GET {} HTTP/1.1\r\nHost: localhost\r\nX-Request-Id: {}\r\nConnection: close\r\n\r\n",
+                   path, request_id.replace('\n', "")).unwrap();
+            let mut resp = String::new();
+            client.read_to_string(&mut resp).unwrap();
+        }
+
+        // The line of a streamed body is written when the body is finished.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let lines = loop {
+            let mut s = String::new();
+            fs::File::open(&path).unwrap().read_to_string(&mut s).unwrap();
+            let lines: Vec<Json> = s.lines().map(|x| serde_json::from_str(x).unwrap()).collect();
+            if lines.len() == requests.len() || Instant::now() >= deadline {
+                break lines;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        let _ = fs::remove_file(&path);
+        shutdown.shutdown();
+        assert!(server.join().unwrap().is_ok());
+
+        let find = |path: &str| lines.iter().find(|x| x["uri"] == path).cloned().unwrap();
+        assert_eq!(find("/fail")["status"], 500);
+        assert_eq!(find("/fail")["request_id"], "abc-1");
+        assert_eq!(find("/panic")["status"], 500);
+        assert_eq!(find("/missing")["status"], 404);
+        assert_ne!(find("/missing")["request_id"], "abc\"3");
+        assert_eq!(find("/stream")["status"], 200);
+        assert_eq!(find("/stream")["size"], 6);
     }
 }